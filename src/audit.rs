@@ -0,0 +1,130 @@
+//! `unpak build --audit`: checking that a build only wrote to its staging tree.
+//!
+//! The writable host directories of the build sandbox other than the staging tree, which is
+//! just the source tree, are snapshotted before the build and compared with how they are after
+//! it. Anything created, modified or removed there is reported, except for what the tree's
+//! `.unpakignore` lists, which is where in-tree builds declare the outputs they are expected to
+//! write. Reads of the host aren't tracked; the mounts already decide what a build can read.
+
+use crate::cache::{is_ignored, read_ignore_file};
+use glob::Pattern;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// What a file looked like: its modification time in nanoseconds, its size and inode.
+type Stamp = (i128, u64, u64);
+
+/// The files of a directory tree at one point in time.
+pub struct Snapshot {
+    root: PathBuf,
+    ignored: Vec<Pattern>,
+    files: BTreeMap<PathBuf, Stamp>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A file the build changed outside its staging tree.
+#[derive(Debug)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ChangeKind::Created => "created",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Removed => "removed",
+        };
+        write!(f, "{kind} {}", self.path.display())
+    }
+}
+
+/// Records every file under `root`, leaving out what its `.unpakignore` lists. Symlinks are
+/// recorded rather than followed.
+pub fn snapshot(root: &Path) -> io::Result<Snapshot> {
+    let ignored = read_ignore_file(root)?;
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![root.to_owned()];
+    while let Some(current) = dirs.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative = path.strip_prefix(root).expect("walked from root");
+            if is_ignored(relative, &ignored) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let mtime =
+                i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec());
+            files.insert(
+                relative.to_owned(),
+                (mtime, metadata.size(), metadata.ino()),
+            );
+        }
+    }
+    Ok(Snapshot {
+        root: root.to_owned(),
+        ignored,
+        files,
+    })
+}
+
+impl Snapshot {
+    /// Takes a new snapshot of the same tree, with the same files left out.
+    pub fn retake(&self) -> io::Result<Snapshot> {
+        let mut after = snapshot(&self.root)?;
+        // a build that rewrites the ignore file mustn't hide its own writes
+        if after.ignored != self.ignored {
+            after = Snapshot {
+                files: after
+                    .files
+                    .into_iter()
+                    .filter(|(path, _)| !is_ignored(path, &self.ignored))
+                    .collect(),
+                ignored: self.ignored.clone(),
+                ..after
+            };
+        }
+        Ok(after)
+    }
+
+    /// What changed between this snapshot and `after`, by path.
+    pub fn changes(&self, after: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for (path, stamp) in &self.files {
+            let kind = match after.files.get(path) {
+                None => ChangeKind::Removed,
+                Some(now) if now != stamp => ChangeKind::Modified,
+                Some(_) => continue,
+            };
+            changes.push(Change {
+                path: self.root.join(path),
+                kind,
+            });
+        }
+        for path in after.files.keys() {
+            if !self.files.contains_key(path) {
+                changes.push(Change {
+                    path: self.root.join(path),
+                    kind: ChangeKind::Created,
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        changes
+    }
+}
@@ -0,0 +1,421 @@
+//! A content-addressed store of build results.
+//!
+//! A project's cache key hashes everything its build depends on: the manifest itself, the
+//! checksum or commit of its source, the projects it was built against and the build options
+//! that change what its sandbox sees. Two builds with the same key are assumed to produce the
+//! same tree, so a stored result can stand in for running the build.
+//! Projects without a `source` are built from their own directory, the manifest's or the one
+//! named after it in the registry, so for them the key also hashes that tree, which is how
+//! unchanged local projects skip rebuilding. Fetched sources are pinned by their checksum or
+//! commit and unpacked afresh for each build, so nothing else is hashed.
+//!
+//! Files the build itself writes into the tree shouldn't count, or every build would invalidate
+//! the next. They can be listed in a `.unpakignore` at the top of the tree, one glob per line
+//! with `#` starting a comment. A pattern containing a `/` is matched against the whole path
+//! relative to the tree, others against each file or directory name, and a trailing `/` is
+//! ignored. A matching directory is skipped along with everything in it.
+//!
+//! The shared libraries `ldd` finds for a program are cached here too, keyed by the program's
+//! path, size and modification time.
+
+use crate::lock::LOCKFILE_NAME;
+use crate::{BuildOptions, ProjectId, SourceProject};
+use glob::Pattern;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The cache directory set by the config file, which takes precedence over the environment.
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes [`cache_dir`] return `dir` for the rest of the process. Only the first call counts.
+pub fn set_cache_dir(dir: PathBuf) {
+    if CACHE_DIR.set(dir).is_err() {
+        warn!("cache directory already set, ignoring");
+    }
+}
+
+/// The directory unpak caches downloads and builds in: the one from [`set_cache_dir`], or else
+/// `$XDG_CACHE_HOME/unpak` or `~/.cache/unpak`.
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = CACHE_DIR.get() {
+        return dir.clone();
+    }
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    cache.join("unpak")
+}
+
+/// Hashes the inputs of building `project` against `deps` with `options` into a hex encoded key.
+/// `tree` is the [`source_tree_hash`] of a project built in its own directory, as those without
+/// a `source` are.
+///
+/// `deps` are the ids of the build dependencies along with their own keys, so that a change to
+/// any project changes the keys of everything built on top of it, however indirectly, and
+/// nothing else. Their order doesn't matter. Of `options`, only those that change the sandbox
+/// or the commands run in it count.
+pub fn cache_key(
+    project: &SourceProject,
+    deps: &[(ProjectId, String)],
+    tree: Option<&str>,
+    options: &BuildOptions,
+) -> String {
+    let mut hasher = Sha256::new();
+    let manifest = serde_json::to_vec(project).expect("manifests serialize to JSON");
+    hasher.update(&manifest);
+    hasher.update([0]);
+    if let Some(source) = &project.source {
+        hasher.update(source.pin().to_ascii_lowercase());
+    }
+    if let Some(tree) = tree {
+        hasher.update([0]);
+        hasher.update(tree);
+    }
+    if let Some(source_date) = options.source_date_epoch {
+        hasher.update([1]);
+        hasher.update(source_date.to_le_bytes());
+    }
+    // whether builds reach the network, expand unset variables, have the merged-/usr symlinks
+    // and write to a terminal
+    hasher.update([
+        2,
+        u8::from(options.share_net),
+        u8::from(options.allow_unset_vars),
+        u8::from(options.no_std_symlinks),
+        u8::from(options.raw_output),
+    ]);
+
+    let mut deps: Vec<(&str, &str)> = deps
+        .iter()
+        .map(|(dep, key)| (dep.0.as_str(), key.as_str()))
+        .collect();
+    deps.sort_unstable();
+    deps.dedup();
+    for (dep, key) in deps {
+        hasher.update([0]);
+        hasher.update(dep);
+        hasher.update([0]);
+        hasher.update(key);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The name of the file listing what [`source_tree_hash`] leaves out.
+pub const IGNORE_FILE_NAME: &str = ".unpakignore";
+
+/// The patterns of the `.unpakignore` in `dir`, if it has one.
+pub fn read_ignore_file(dir: &Path) -> io::Result<Vec<Pattern>> {
+    let text = match std::fs::read_to_string(dir.join(IGNORE_FILE_NAME)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            Pattern::new(line.trim_end_matches('/')).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{IGNORE_FILE_NAME}: invalid pattern {line}: {e}"),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Whether `relative`, a path inside the tree, is left out of its hash.
+pub fn is_ignored(relative: &Path, ignored: &[Pattern]) -> bool {
+    if relative == Path::new(LOCKFILE_NAME) {
+        return true;
+    }
+    let name = relative.file_name().unwrap_or_default();
+    ignored.iter().any(|pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches_path(relative)
+        } else {
+            pattern.matches_path(Path::new(name))
+        }
+    })
+}
+
+/// Hashes the paths, modification times and contents of everything in the tree at `dir` into a
+/// hex encoded string, leaving out what its `.unpakignore` lists and the lockfile unpak writes
+/// there. Symlinks are hashed by their target rather than followed.
+pub fn source_tree_hash(dir: &Path) -> io::Result<String> {
+    // a registry project's directory is easy to forget to create
+    if let Err(e) = std::fs::metadata(dir) {
+        return Err(io::Error::new(e.kind(), format!("{}: {e}", dir.display())));
+    }
+    let ignored = read_ignore_file(dir)?;
+    let mut hasher = Sha256::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        let mut entries = std::fs::read_dir(&current)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        // pushed in reverse so the walk visits them in order
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            let relative = path.strip_prefix(dir).expect("walked from dir");
+            if is_ignored(relative, &ignored) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                subdirs.push(path);
+                continue;
+            }
+
+            hasher.update(relative.as_os_str().as_bytes());
+            hasher.update([0]);
+            hasher.update(metadata.mtime().to_le_bytes());
+            hasher.update(metadata.mtime_nsec().to_le_bytes());
+            if metadata.is_symlink() {
+                hasher.update(std::fs::read_link(&path)?.as_os_str().as_bytes());
+            } else if metadata.is_file() {
+                io::copy(&mut std::fs::File::open(&path)?, &mut hasher)?;
+            }
+            hasher.update([0]);
+        }
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Somewhere build results can be kept and found again by their cache key.
+pub trait CacheStore {
+    /// Copies the stored result for `key` to `dest`. Returns `false` if there is none.
+    fn restore(&self, key: &str, dest: &Path) -> io::Result<bool>;
+
+    /// Stores a copy of the tree at `outputs` as the result for `key`.
+    fn store(&self, key: &str, outputs: &Path) -> io::Result<()>;
+}
+
+/// A [`CacheStore`] keeping each result as a directory named by its key.
+pub struct FsCache {
+    root: PathBuf,
+}
+
+impl FsCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FsCache { root: root.into() }
+    }
+
+    /// The cache in `<cache dir>/builds`.
+    pub fn open_default() -> Self {
+        FsCache::new(cache_dir().join("builds"))
+    }
+}
+
+impl CacheStore for FsCache {
+    fn restore(&self, key: &str, dest: &Path) -> io::Result<bool> {
+        let entry = self.root.join(key);
+        if !entry.is_dir() {
+            return Ok(false);
+        }
+        copy_tree(&entry, dest)?;
+        Ok(true)
+    }
+
+    fn store(&self, key: &str, outputs: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(&self.root)?;
+        // copy next to the entry and rename it into place, so a build interrupted halfway
+        // through storing never leaves a partial result behind
+        let partial = self
+            .root
+            .join(format!("{key}.partial-{}", std::process::id()));
+        copy_tree(outputs, &partial)?;
+        let entry = self.root.join(key);
+        if entry.exists() {
+            std::fs::remove_dir_all(&entry)?;
+        }
+        std::fs::rename(&partial, &entry)
+    }
+}
+
+/// Recursively copies the directory `src` into `dst`, recreating symlinks rather than following
+/// them.
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let to = dst.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &to)?;
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(entry.path())?;
+            if to.symlink_metadata().is_ok() {
+                std::fs::remove_file(&to)?;
+            }
+            std::os::unix::fs::symlink(target, &to)?;
+        } else {
+            std::fs::copy(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Where resolved shared library lists are cached, one file per program version.
+fn ldd_dir() -> PathBuf {
+    cache_dir().join("ldd")
+}
+
+/// Identifies a version of the program at `path` by its path, size and modification time.
+fn program_key(path: &Path) -> io::Result<String> {
+    let metadata = std::fs::metadata(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().as_bytes());
+    hasher.update([0]);
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(metadata.mtime().to_le_bytes());
+    hasher.update(metadata.mtime_nsec().to_le_bytes());
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// The shared libraries of `program`, from the on-disk cache if it hasn't changed since they were
+/// last resolved, otherwise from `resolve`.
+///
+/// A cached list is only used while all of its libraries still exist. Failing to read or write
+/// the cache isn't an error; the libraries are resolved again instead.
+pub fn cached_shared_libs(
+    program: &Path,
+    resolve: impl FnOnce(&Path) -> io::Result<Vec<PathBuf>>,
+) -> io::Result<Vec<PathBuf>> {
+    let entry = match program_key(program) {
+        Ok(key) => ldd_dir().join(key),
+        Err(e) => {
+            debug!("not caching the libraries of {}: {e}", program.display());
+            return resolve(program);
+        }
+    };
+
+    let cached = std::fs::read(&entry)
+        .ok()
+        .and_then(|text| serde_json::from_slice::<Vec<PathBuf>>(&text).ok());
+    if let Some(libs) = cached {
+        if libs.iter().all(|lib| lib.exists()) {
+            return Ok(libs);
+        }
+    }
+
+    let libs = resolve(program)?;
+    let stored = std::fs::create_dir_all(ldd_dir())
+        .and_then(|()| serde_json::to_vec(&libs).map_err(io::Error::from))
+        .and_then(|text| {
+            // written aside and renamed, so concurrent builds never read half an entry
+            let partial = entry.with_extension(format!("partial-{}", std::process::id()));
+            std::fs::write(&partial, text)?;
+            std::fs::rename(&partial, &entry)
+        });
+    if let Err(e) = stored {
+        warn!(
+            "could not cache the libraries of {}: {e}",
+            program.display()
+        );
+    }
+    Ok(libs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    fn project(program: &str) -> SourceProject {
+        toml::from_str(&format!(
+            "id = \"foo\"\nrdeps = []\nbdeps = []\n\
+             [[build.Cmds]]\nprogram = \"{program}\"\narguments = []\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn keys_change_with_what_the_build_depends_on() {
+        let options = BuildOptions::default();
+        let deps = [
+            (ProjectId::from("a"), "1".to_owned()),
+            (ProjectId::from("b"), "2".to_owned()),
+        ];
+        let base = cache_key(&project("make"), &deps, Some("tree"), &options);
+
+        let reversed = [deps[1].clone(), deps[0].clone()];
+        assert_eq!(
+            cache_key(&project("make"), &reversed, Some("tree"), &options),
+            base
+        );
+        let timed = BuildOptions {
+            timings: true,
+            ..BuildOptions::default()
+        };
+        assert_eq!(
+            cache_key(&project("make"), &deps, Some("tree"), &timed),
+            base
+        );
+
+        assert_ne!(
+            cache_key(&project("ninja"), &deps, Some("tree"), &options),
+            base
+        );
+        assert_ne!(
+            cache_key(&project("make"), &deps[..1], Some("tree"), &options),
+            base
+        );
+        let rebuilt = [deps[0].clone(), (ProjectId::from("b"), "3".to_owned())];
+        assert_ne!(
+            cache_key(&project("make"), &rebuilt, Some("tree"), &options),
+            base
+        );
+        assert_ne!(
+            cache_key(&project("make"), &deps, Some("other"), &options),
+            base
+        );
+        let networked = BuildOptions {
+            share_net: true,
+            ..BuildOptions::default()
+        };
+        assert_ne!(
+            cache_key(&project("make"), &deps, Some("tree"), &networked),
+            base
+        );
+    }
+
+    #[test]
+    fn tree_hashes_skip_ignored_files() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("main.c"), "int main;").unwrap();
+        std::fs::write(
+            dir.path().join(IGNORE_FILE_NAME),
+            "# outputs\n*.o\nbuild/\n",
+        )
+        .unwrap();
+        let hash = source_tree_hash(dir.path()).unwrap();
+
+        std::fs::write(dir.path().join("main.o"), "object").unwrap();
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        std::fs::write(dir.path().join("build").join("main"), "program").unwrap();
+        assert_eq!(source_tree_hash(dir.path()).unwrap(), hash);
+
+        std::fs::write(dir.path().join("util.c"), "int util;").unwrap();
+        assert_ne!(source_tree_hash(dir.path()).unwrap(), hash);
+    }
+}
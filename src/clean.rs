@@ -0,0 +1,120 @@
+//! Reclaiming disk space from unpak's own directories.
+
+use crate::cache::cache_dir;
+use crate::stage::staging_root;
+use log::{debug, warn};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Removes the staging trees and unpacked sources of built projects and what interrupted builds
+/// and downloads left behind, and with `all` the build cache, cached library lists and fetched
+/// sources too. Returns the number of bytes freed.
+pub fn clean(all: bool) -> io::Result<u64> {
+    let mut freed = 0;
+
+    let temp = std::env::temp_dir();
+    for entry in read_dir_or_empty(&temp)? {
+        let path = entry?.path();
+        if is_abandoned_scratch(&path) {
+            freed += remove_within(&path, &temp)?;
+        }
+    }
+
+    let cache = cache_dir();
+    for dir in [cache.join("builds"), cache.join("sources")] {
+        for entry in read_dir_or_empty(&dir)? {
+            let path = entry?.path();
+            let partial = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().contains(".partial-"));
+            if all || partial {
+                freed += remove_within(&path, &cache)?;
+            }
+        }
+    }
+
+    freed += remove_within(&staging_root(), &cache)?;
+    freed += remove_within(&cache.join("unpacked"), &cache)?;
+    if all {
+        freed += remove_within(&cache.join("ldd"), &cache)?;
+    }
+    Ok(freed)
+}
+
+fn read_dir_or_empty(
+    dir: &Path,
+) -> io::Result<impl Iterator<Item = io::Result<std::fs::DirEntry>>> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => Ok(Some(entries).into_iter().flatten()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None.into_iter().flatten()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `path` is a build's scratch directory, named `unpak-<pid>-<n>`, whose process is
+/// gone.
+fn is_abandoned_scratch(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let Some((pid, n)) = name
+        .strip_prefix("unpak-")
+        .and_then(|rest| rest.split_once('-'))
+    else {
+        return false;
+    };
+    let (Ok(pid), Ok(_)) = (pid.parse::<u32>(), n.parse::<usize>()) else {
+        return false;
+    };
+    pid != std::process::id() && !Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Removes `path` and everything under it, but only if it really is inside `root` once
+/// symlinks are resolved. Returns the number of bytes freed.
+fn remove_within(path: &Path, root: &Path) -> io::Result<u64> {
+    let (path, root) = match (path.canonicalize(), root.canonicalize()) {
+        (Ok(path), Ok(root)) => (path, root),
+        (Err(e), _) | (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+    if path == root || !path.starts_with(&root) {
+        warn!(
+            "not removing {}, it is outside of {}",
+            path.display(),
+            root.display()
+        );
+        return Ok(0);
+    }
+
+    let size = disk_usage(&path)?;
+    debug!("removing {}", path.display());
+    if path.is_dir() {
+        std::fs::remove_dir_all(&path)?;
+    } else {
+        std::fs::remove_file(&path)?;
+    }
+    Ok(size)
+}
+
+/// The apparent size of `path` and, for directories, everything under it. Symlinks count as
+/// themselves and aren't followed.
+fn disk_usage(path: &Path) -> io::Result<u64> {
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    let mut dirs: Vec<PathBuf> = vec![path.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,99 @@
+//! Defaults for every run, read from `$XDG_CONFIG_HOME/unpak/config.toml` or
+//! `~/.config/unpak/config.toml`:
+//!
+//! ```toml
+//! cache_dir = "/var/cache/unpak"
+//! registry = "/srv/unpak/registry"
+//! jobs = 4
+//! unshare_net = true
+//! bwrap_path = "/opt/bubblewrap/bin/bwrap"
+//! ```
+//!
+//! Every setting is optional, and the command-line flags take precedence over them. Without a
+//! config file, unpak behaves as if it were empty.
+
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where to cache downloads and builds, instead of `$XDG_CACHE_HOME/unpak`.
+    pub cache_dir: Option<PathBuf>,
+    /// The registry used without `--registry`.
+    pub registry: Option<PathBuf>,
+    /// How many projects to build at once without `--jobs`.
+    pub jobs: Option<usize>,
+    /// Whether build sandboxes get a network namespace of their own, cut off from the host's
+    /// network, without `--share-net` or `--unshare-net`. On unless set to `false`.
+    pub unshare_net: Option<bool>,
+    /// The bwrap to run, unless `--bwrap-path` or `$UNPAK_BWRAP` name another.
+    pub bwrap_path: Option<PathBuf>,
+}
+
+/// A config file that couldn't be used.
+#[derive(Debug)]
+pub struct ConfigError {
+    path: PathBuf,
+    failure: ConfigFailure,
+}
+
+#[derive(Debug)]
+enum ConfigFailure {
+    Read(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.failure {
+            ConfigFailure::Read(e) => write!(f, "could not read config {path}: {e}"),
+            ConfigFailure::Parse(e) => write!(f, "invalid config {path}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.failure {
+            ConfigFailure::Read(e) => Some(e),
+            ConfigFailure::Parse(e) => Some(e),
+        }
+    }
+}
+
+/// Where the config file is looked for.
+pub fn config_path() -> PathBuf {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config.join("unpak").join("config.toml")
+}
+
+impl Config {
+    /// Reads the config file at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let error = |failure| ConfigError {
+            path: path.to_path_buf(),
+            failure,
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(error(ConfigFailure::Read(e))),
+        };
+        toml::from_str(&text).map_err(|e| error(ConfigFailure::Parse(e)))
+    }
+
+    /// Puts the settings that apply to the whole process, such as the cache directory, into
+    /// effect. Call it before anything touches the cache.
+    pub fn apply(&self) {
+        if let Some(dir) = &self.cache_dir {
+            crate::cache::set_cache_dir(dir.clone());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! `unpak doctor`: checks that the host can run unpak's sandboxes, with hints for what to fix.
+
+use crate::{bwrap_path, bwrap_unshares_user, userns_restriction, INTERPRETER_HOST};
+use std::path::Path;
+
+/// The outcome of one check; `Err` holds a hint on how to fix it.
+struct Check {
+    what: String,
+    result: Result<(), String>,
+}
+
+fn tool(name: &str, package: &str, purpose: &str) -> Check {
+    Check {
+        what: format!("{name} is on PATH"),
+        result: match which::which(name) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!(
+                "install the `{package}` package; unpak needs it {purpose}"
+            )),
+        },
+    }
+}
+
+/// bwrap from `PATH`, or the one configured in its place.
+fn bwrap() -> Check {
+    let path = bwrap_path();
+    if path == Path::new("bwrap") {
+        return tool("bwrap", "bubblewrap", "to create sandboxes");
+    }
+    Check {
+        what: format!("bwrap is at {}", path.display()),
+        result: match which::which(&path) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("point --bwrap-path, bwrap_path or UNPAK_BWRAP at bwrap".to_owned()),
+        },
+    }
+}
+
+/// Whether unprivileged users may create user namespaces, which bwrap relies on when it isn't
+/// setuid. A restricting sysctl only fails the check if bwrap is held back by it, as opposed to
+/// being allowed by an AppArmor profile of its own.
+fn user_namespaces() -> Check {
+    Check {
+        what: "unprivileged user namespaces are enabled".to_owned(),
+        result: match userns_restriction() {
+            Some(restriction) if !bwrap_unshares_user(&bwrap_path()) => Err(format!(
+                "enable them with `sysctl {}={}`, or install bwrap setuid",
+                restriction.sysctl, restriction.allow
+            )),
+            _ => Ok(()),
+        },
+    }
+}
+
+fn interpreter() -> Check {
+    Check {
+        what: format!("the dynamic loader exists at {INTERPRETER_HOST}"),
+        result: if Path::new(INTERPRETER_HOST).exists() {
+            Ok(())
+        } else {
+            Err("unpak currently only supports x86_64 glibc hosts, which have it there".to_owned())
+        },
+    }
+}
+
+/// Runs every check and prints a report. Returns whether they all passed.
+pub fn doctor() -> bool {
+    let checks = [
+        bwrap(),
+        tool(
+            "ldd",
+            "libc-bin",
+            "to find the shared libraries of sandboxed programs",
+        ),
+        tool("patchelf", "patchelf", "to patch bootstrap programs"),
+        tool("curl", "curl", "to fetch project sources"),
+        tool("git", "git", "to fetch git sources"),
+        user_namespaces(),
+        interpreter(),
+    ];
+
+    let mut healthy = true;
+    for check in &checks {
+        match &check.result {
+            Ok(()) => println!("[ ok ] {}", check.what),
+            Err(hint) => {
+                healthy = false;
+                println!("[FAIL] {}", check.what);
+                println!("       hint: {hint}");
+            }
+        }
+    }
+    healthy
+}
@@ -0,0 +1,199 @@
+//! Just enough ELF parsing to find out how a program is loaded.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_BE: u8 = 2;
+const PT_INTERP: u32 = 3;
+/// The longest `PT_INTERP` accepted, `PATH_MAX` counting its terminating NUL.
+const INTERP_MAX: u64 = 4096;
+
+/// Whether `path` starts with the ELF magic number.
+pub fn is_elf(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads fields of an ELF file in its own word size and byte order.
+struct Reader {
+    file: File,
+    is_64: bool,
+    is_be: bool,
+}
+
+impl Reader {
+    fn bytes<const N: usize>(&mut self, offset: u64) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16(&mut self, offset: u64) -> io::Result<u16> {
+        let buf = self.bytes(offset)?;
+        Ok(if self.is_be {
+            u16::from_be_bytes(buf)
+        } else {
+            u16::from_le_bytes(buf)
+        })
+    }
+
+    fn u32(&mut self, offset: u64) -> io::Result<u32> {
+        let buf = self.bytes(offset)?;
+        Ok(if self.is_be {
+            u32::from_be_bytes(buf)
+        } else {
+            u32::from_le_bytes(buf)
+        })
+    }
+
+    /// A word sized field: 8 bytes in 64-bit files, 4 in 32-bit ones.
+    fn word(&mut self, offset: u64) -> io::Result<u64> {
+        if !self.is_64 {
+            return self.u32(offset).map(u64::from);
+        }
+        let buf = self.bytes(offset)?;
+        Ok(if self.is_be {
+            u64::from_be_bytes(buf)
+        } else {
+            u64::from_le_bytes(buf)
+        })
+    }
+}
+
+/// Returns the interpreter (dynamic loader) requested by the `PT_INTERP` program header of
+/// `path`, or `None` for statically linked programs and files that aren't ELF.
+pub fn elf_interpreter(path: &Path) -> io::Result<Option<PathBuf>> {
+    let mut file = File::open(path)?;
+    let mut ident = [0; 6];
+    match file.read_exact(&mut ident) {
+        Ok(()) if ident[..4] == MAGIC => {}
+        Ok(()) => return Ok(None),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut elf = Reader {
+        file,
+        is_64: ident[4] == CLASS_64,
+        is_be: ident[5] == DATA_BE,
+    };
+    let (phoff, phentsize, phnum) = if elf.is_64 {
+        (elf.word(0x20)?, elf.u16(0x36)?, elf.u16(0x38)?)
+    } else {
+        (elf.word(0x1c)?, elf.u16(0x2a)?, elf.u16(0x2c)?)
+    };
+
+    for i in 0..u64::from(phnum) {
+        // a corrupt header may point anywhere, which reading from then reports
+        let header = phoff.saturating_add(i * u64::from(phentsize));
+        if elf.u32(header)? != PT_INTERP {
+            continue;
+        }
+        let (offset, size) = if elf.is_64 {
+            (elf.word(header + 0x08)?, elf.word(header + 0x20)?)
+        } else {
+            (elf.word(header + 0x04)?, elf.word(header + 0x10)?)
+        };
+
+        if size > INTERP_MAX {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{}: PT_INTERP of {size} bytes is longer than a path can be",
+                    path.display()
+                ),
+            ));
+        }
+        let mut interp = vec![0; size as usize];
+        elf.file.seek(SeekFrom::Start(offset))?;
+        elf.file.read_exact(&mut interp)?;
+        if let Some(nul) = interp.iter().position(|&b| b == 0) {
+            interp.truncate(nul);
+        }
+        if interp.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: empty PT_INTERP", path.display()),
+            ));
+        }
+        return Ok(Some(OsString::from_vec(interp).into()));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[test]
+    fn tells_elf_files_from_others() {
+        let dir = TempDir::new().unwrap();
+        let elf = dir.path().join("program");
+        std::fs::write(&elf, b"\x7fELF\x02\x01\x01\0").unwrap();
+        let script = dir.path().join("script");
+        std::fs::write(&script, "#!/bin/sh\necho hi\n").unwrap();
+        let short = dir.path().join("short");
+        std::fs::write(&short, b"\x7fE").unwrap();
+
+        assert!(is_elf(&elf).unwrap());
+        assert!(!is_elf(&script).unwrap());
+        assert!(!is_elf(&short).unwrap());
+    }
+
+    /// A little-endian 64-bit ELF header with one program header of `p_type`, whose contents
+    /// are `contents`, `size` bytes of them as far as the header says.
+    fn elf64(p_type: u32, contents: &[u8], size: u64) -> Vec<u8> {
+        let mut elf = vec![0; 120];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        elf[64..68].copy_from_slice(&p_type.to_le_bytes());
+        elf[72..80].copy_from_slice(&120u64.to_le_bytes());
+        elf[96..104].copy_from_slice(&size.to_le_bytes());
+        elf.extend_from_slice(contents);
+        elf
+    }
+
+    #[test]
+    fn reads_the_interpreter_of_a_program() {
+        let dir = TempDir::new().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+
+        let loader = b"/lib/ld-musl-x86_64.so.1\0";
+        let dynamic = write("dynamic", &elf64(PT_INTERP, loader, loader.len() as u64));
+        assert_eq!(
+            elf_interpreter(&dynamic).unwrap(),
+            Some(PathBuf::from("/lib/ld-musl-x86_64.so.1"))
+        );
+        // PT_LOAD
+        let static_ = write("static", &elf64(1, &[], 0));
+        assert_eq!(elf_interpreter(&static_).unwrap(), None);
+        let script = write("script", b"#!/bin/sh\n");
+        assert_eq!(elf_interpreter(&script).unwrap(), None);
+
+        let huge = write("huge", &elf64(PT_INTERP, loader, INTERP_MAX + 1));
+        let e = elf_interpreter(&huge).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let empty = write("empty", &elf64(PT_INTERP, b"\0", 1));
+        assert_eq!(
+            elf_interpreter(&empty).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! The errors `unpak` can exit with.
+
+use crate::config::ConfigError;
+use crate::fetch::FetchError;
+use crate::{BuildAllError, CycleError, ManifestError, MissingTool, PlanError, ValidationError};
+use std::fmt;
+use std::io;
+
+/// Everything that can make a subcommand fail, each with its own exit code.
+#[derive(Debug)]
+pub enum UnpakError {
+    Manifest(ManifestError),
+    /// The manifest of the project to build, or its id in the registry, and everything
+    /// [`validate`](crate::SourceProject::validate) found wrong with it.
+    Invalid {
+        manifest: String,
+        errors: Vec<ValidationError>,
+    },
+    /// The sandbox couldn't be set up or started.
+    Sandbox(io::Error),
+    Build(BuildAllError),
+    Fetch(FetchError),
+    MissingTool(MissingTool),
+    Cycle(CycleError),
+    /// The dependencies of a project can't be satisfied.
+    Plan(PlanError),
+    Config(ConfigError),
+    /// Reading or writing unpak's own files, such as lockfiles or the event log, failed.
+    Io {
+        context: String,
+        source: io::Error,
+    },
+}
+
+impl UnpakError {
+    /// Wraps an I/O error with what unpak was doing, as in `map_err(UnpakError::io("..."))`.
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| UnpakError::Io { context, source }
+    }
+
+    /// The process exit code for this error:
+    ///
+    /// - 1 when a build failed, or for anything not listed below,
+    /// - 2 for manifests, dependencies and config files that can't be used,
+    /// - 3 when a source couldn't be fetched,
+    /// - 4 when the host can't run sandboxes.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UnpakError::Build(_) | UnpakError::Io { .. } => 1,
+            UnpakError::Manifest(_)
+            | UnpakError::Invalid { .. }
+            | UnpakError::Cycle(_)
+            | UnpakError::Plan(_)
+            | UnpakError::Config(_) => 2,
+            UnpakError::Fetch(_) => 3,
+            UnpakError::Sandbox(_) | UnpakError::MissingTool(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for UnpakError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnpakError::Manifest(e) => e.fmt(f),
+            UnpakError::Invalid { manifest, errors } => {
+                write!(f, "invalid manifest {manifest}:")?;
+                for error in errors {
+                    write!(f, "\n    {error}")?;
+                }
+                Ok(())
+            }
+            UnpakError::Sandbox(e) => write!(f, "could not launch sandbox: {e}"),
+            UnpakError::Build(e) => e.fmt(f),
+            UnpakError::Fetch(e) => e.fmt(f),
+            UnpakError::MissingTool(e) => e.fmt(f),
+            UnpakError::Cycle(e) => e.fmt(f),
+            UnpakError::Plan(e) => e.fmt(f),
+            UnpakError::Config(e) => e.fmt(f),
+            UnpakError::Io { context, source } => write!(f, "{context}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for UnpakError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UnpakError::Manifest(e) => Some(e),
+            UnpakError::Invalid { .. } => None,
+            UnpakError::Sandbox(e) => Some(e),
+            UnpakError::Build(e) => Some(e),
+            UnpakError::Fetch(e) => Some(e),
+            UnpakError::MissingTool(e) => Some(e),
+            UnpakError::Cycle(e) => Some(e),
+            UnpakError::Plan(e) => Some(e),
+            UnpakError::Config(e) => Some(e),
+            UnpakError::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ManifestError> for UnpakError {
+    fn from(e: ManifestError) -> Self {
+        UnpakError::Manifest(e)
+    }
+}
+
+impl From<BuildAllError> for UnpakError {
+    fn from(e: BuildAllError) -> Self {
+        UnpakError::Build(e)
+    }
+}
+
+impl From<FetchError> for UnpakError {
+    fn from(e: FetchError) -> Self {
+        UnpakError::Fetch(e)
+    }
+}
+
+impl From<MissingTool> for UnpakError {
+    fn from(e: MissingTool) -> Self {
+        UnpakError::MissingTool(e)
+    }
+}
+
+impl From<CycleError> for UnpakError {
+    fn from(e: CycleError) -> Self {
+        UnpakError::Cycle(e)
+    }
+}
+
+impl From<PlanError> for UnpakError {
+    fn from(e: PlanError) -> Self {
+        match e {
+            PlanError::Cycle(e) => UnpakError::Cycle(e),
+            e => UnpakError::Plan(e),
+        }
+    }
+}
+
+impl From<ConfigError> for UnpakError {
+    fn from(e: ConfigError) -> Self {
+        UnpakError::Config(e)
+    }
+}
@@ -0,0 +1,71 @@
+//! A machine-readable log of what unpak did, written as JSON Lines.
+//!
+//! Like the `log` crate, the event log is global: it is opened once with [`init`] and every
+//! [`record`] afterwards appends one JSON object per line, flushed immediately so the file can
+//! be tailed during long builds. Without [`init`], recording does nothing.
+
+use crate::ProjectId;
+use log::warn;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+static LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    CommandStarted {
+        project: &'a ProjectId,
+        command: String,
+    },
+    CommandFinished {
+        project: &'a ProjectId,
+        command: String,
+        /// `None` if the command was killed by a signal.
+        exit_code: Option<i32>,
+        /// The signal that killed the command, if one did.
+        signal: Option<i32>,
+    },
+    MountAdded {
+        /// The bwrap option used, such as `ro-bind` or `tmpfs`.
+        kind: &'static str,
+        host: Option<&'a Path>,
+        sbx: &'a Path,
+    },
+    SandboxExited {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    },
+}
+
+/// Starts appending events to the file at `path`.
+pub fn init(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if LOG.set(Mutex::new(file)).is_err() {
+        warn!("event log already initialized, ignoring {}", path.display());
+    }
+    Ok(())
+}
+
+pub fn record(event: &Event) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+
+    let mut line = match serde_json::to_vec(event) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("could not serialize event: {e}");
+            return;
+        }
+    };
+    line.push(b'\n');
+
+    let mut file = log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Err(e) = file.write_all(&line).and_then(|()| file.flush()) {
+        warn!("could not write to the event log: {e}");
+    }
+}
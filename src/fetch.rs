@@ -0,0 +1,582 @@
+//! Downloading and verifying project sources: archives with curl, and repositories with git.
+
+use crate::ProjectId;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Where a project's source comes from, told apart by its fields: `url` and `sha256` for an
+/// archive, `git` and `rev` for a repository.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SourceSpec {
+    Archive(ArchiveSource),
+    Git(GitSource),
+}
+
+/// A source archive to download.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveSource {
+    /// A URL, or a path relative to the manifest's directory.
+    pub url: String,
+    /// Hex encoded SHA-256 of the archive.
+    pub sha256: String,
+}
+
+impl ArchiveSource {
+    /// Whether `sha256` is a checksum at all: 64 lowercase hex digits. It names the downloaded
+    /// file under [`sources_dir`], so anything else could name a file outside it.
+    pub fn checksum_is_valid(&self) -> bool {
+        self.sha256.len() == 64
+            && self
+                .sha256
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    }
+}
+
+/// A git repository, checked out at a pinned commit.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GitSource {
+    /// The URL of the repository, or a path relative to the manifest's directory.
+    pub git: String,
+    /// The full hex hash of the commit to check out. Branches and tags aren't accepted, since
+    /// they can move.
+    pub rev: String,
+}
+
+impl GitSource {
+    /// Whether `rev` is a full commit hash: 40 hex digits, or 64 for SHA-256 repositories. It
+    /// names the checkout under [`sources_dir`], which is removed before fetching again.
+    pub fn rev_is_valid(&self) -> bool {
+        matches!(self.rev.len(), 40 | 64) && self.rev.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+impl SourceSpec {
+    /// Where the source is fetched from.
+    pub fn location(&self) -> &str {
+        match self {
+            SourceSpec::Archive(archive) => &archive.url,
+            SourceSpec::Git(git) => &git.git,
+        }
+    }
+
+    /// The hash that pins the contents of the source: the archive checksum or the commit.
+    pub fn pin(&self) -> &str {
+        match self {
+            SourceSpec::Archive(archive) => &archive.sha256,
+            SourceSpec::Git(git) => &git.rev,
+        }
+    }
+
+    /// Where the source is fetched to: the archive file, or the checkout directory.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            SourceSpec::Archive(archive) => sources_dir().join(&archive.sha256),
+            SourceSpec::Git(git) => sources_dir().join("git").join(git.rev.to_ascii_lowercase()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    Io(io::Error),
+    /// The downloader exited unsuccessfully.
+    Download {
+        url: String,
+        status: ExitStatus,
+        /// The HTTP status of the response, if the server sent one.
+        http_status: Option<u16>,
+        /// What the downloader printed about the failure.
+        message: String,
+    },
+    Mismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// A git command fetching a repository exited unsuccessfully.
+    Git {
+        url: String,
+        status: ExitStatus,
+        /// What git printed about the failure.
+        message: String,
+    },
+    /// The checkout of a repository isn't at the commit asked for.
+    WrongRevision {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// tar couldn't unpack a fetched archive.
+    Unpack {
+        archive: PathBuf,
+        status: ExitStatus,
+        /// What tar printed about the failure.
+        message: String,
+    },
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Io(e) => write!(f, "could not fetch source: {e}"),
+            FetchError::Download {
+                url,
+                status,
+                http_status,
+                message,
+            } => {
+                write!(f, "downloading {url} failed: ")?;
+                match (http_status, message.is_empty()) {
+                    (Some(code), _) if *code >= 400 => write!(f, "HTTP {code}"),
+                    (_, false) => f.write_str(message),
+                    _ => write!(f, "curl {status}"),
+                }
+            }
+            FetchError::Mismatch {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "checksum mismatch for {url}: expected sha256 {expected}, got {actual}"
+            ),
+            FetchError::Git {
+                url,
+                status,
+                message,
+            } => {
+                write!(f, "fetching {url} failed: ")?;
+                match message.is_empty() {
+                    false => f.write_str(message),
+                    true => write!(f, "git {status}"),
+                }
+            }
+            FetchError::WrongRevision {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{url} checked out commit {actual} instead of {expected}; rev must be a full commit hash"
+            ),
+            FetchError::Unpack {
+                archive,
+                status,
+                message,
+            } => {
+                write!(f, "unpacking {} failed: ", archive.display())?;
+                match message.is_empty() {
+                    false => f.write_str(message),
+                    true => write!(f, "tar {status}"),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl FetchError {
+    /// Whether trying again might succeed: I/O and network trouble or a server error, but never
+    /// a checksum mismatch or a missing file.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Io(_) => true,
+            FetchError::Download {
+                status,
+                http_status,
+                ..
+            } => match http_status {
+                Some(code) if *code >= 400 => *code >= 500 || *code == 429,
+                // couldn't resolve host, couldn't connect, timed out, or the connection broke
+                _ => matches!(status.code(), Some(6 | 7 | 28 | 35 | 52 | 55 | 56)),
+            },
+            // git exits with 128 for everything, so the message is all there is to go by
+            FetchError::Git { message, .. } => [
+                "Could not resolve host",
+                "Failed to connect",
+                "Connection reset",
+                "Connection timed out",
+                "early EOF",
+            ]
+            .iter()
+            .any(|cause| message.contains(cause)),
+            FetchError::Mismatch { .. }
+            | FetchError::WrongRevision { .. }
+            | FetchError::Unpack { .. } => false,
+        }
+    }
+}
+
+impl From<io::Error> for FetchError {
+    fn from(e: io::Error) -> Self {
+        FetchError::Io(e)
+    }
+}
+
+/// Directory fetched archives are stored in, named by their checksum.
+pub fn sources_dir() -> PathBuf {
+    crate::cache::cache_dir().join("sources")
+}
+
+/// Where the archive source of `id` is unpacked for its build, afresh every time since builds
+/// write into their source tree.
+pub fn unpack_dir(id: &ProjectId) -> PathBuf {
+    crate::cache::cache_dir().join("unpacked").join(&id.0)
+}
+
+/// Unpacks `archive` into a fresh `dest` with tar, which tells the compression from the contents.
+pub fn unpack(archive: &Path, dest: &Path) -> Result<(), FetchError> {
+    match std::fs::remove_dir_all(dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    std::fs::create_dir_all(dest)?;
+    debug!("unpacking {} into {}", archive.display(), dest.display());
+    let output = Command::new("tar")
+        .arg("--extract")
+        .arg("--no-same-owner")
+        .arg("--file")
+        .arg(archive)
+        .arg("--directory")
+        .arg(dest)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message: Vec<&str> = stderr
+            .lines()
+            .map(|line| line.trim_start_matches("tar: "))
+            .filter(|line| !line.is_empty())
+            .collect();
+        return Err(FetchError::Unpack {
+            archive: archive.to_owned(),
+            status: output.status,
+            message: message.join("; "),
+        });
+    }
+    Ok(())
+}
+
+/// The top of the source tree unpacked into `dir`: the one directory the archive holds, as
+/// `foo-1.0/` in `foo-1.0.tar.gz`, or else `dir` itself.
+pub fn source_root(dir: &Path) -> io::Result<PathBuf> {
+    let mut entries = std::fs::read_dir(dir)?;
+    match (entries.next().transpose()?, entries.next()) {
+        (Some(only), None) if only.file_type()?.is_dir() => Ok(only.path()),
+        _ => Ok(dir.to_owned()),
+    }
+}
+
+/// The hex encoded SHA-256 of the file at `path`.
+fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Fetches `spec` to `dest` with [`fetch_and_verify`] or [`fetch_git`], trying again up to
+/// `attempts` times in all when that fails in a way that might not happen again, waiting
+/// `backoff` before the first retry and twice as long before each one after. `timeout` only
+/// limits archive downloads.
+pub fn fetch_with_retry(
+    spec: &SourceSpec,
+    dest: &Path,
+    attempts: u32,
+    backoff: Duration,
+    timeout: Option<Duration>,
+) -> Result<(), FetchError> {
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        let result = match spec {
+            SourceSpec::Archive(archive) => fetch_and_verify(archive, dest, timeout),
+            SourceSpec::Git(git) => fetch_git(git, dest),
+        };
+        match result {
+            Err(e) if e.is_transient() && attempt < attempts => {
+                warn!(
+                    "{e}; retrying in {}s ({attempt}/{})",
+                    delay.as_secs_f32(),
+                    attempts - 1
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Prefix of the line curl is asked to finish its stderr with, holding the HTTP status.
+const HTTP_STATUS_MARKER: &str = "unpak-http-status:";
+/// Prefix of the line after it, holding the `Last-Modified` header of the response, if any.
+const LAST_MODIFIED_MARKER: &str = "unpak-last-modified:";
+
+/// Seconds since the epoch of an HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(date: &str) -> Option<u64> {
+    let [_, day, month, year, time, "GMT"] = date.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|&name| name == month)? as i64 + 1;
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (hms.next()??, hms.next()??, hms.next()??);
+
+    // days since 1970-01-01 of the civil date, counting years from March so leap days come last
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    u64::try_from(days * 86_400 + hours * 3_600 + minutes * 60 + seconds).ok()
+}
+
+/// Downloads `spec.url` to `dest`, hashing it as it streams in, unless `dest` already holds the
+/// archive with that checksum. The download is abandoned if it takes longer than `timeout`.
+///
+/// `dest` is dated like the file on the server, if the server says, for the `SOURCE_DATE_EPOCH`
+/// of builds.
+///
+/// The archive is downloaded next to `dest` and only renamed into place once its checksum
+/// matches the one in `spec`, so `dest` never holds a partial or wrong download.
+pub fn fetch_and_verify(
+    spec: &ArchiveSource,
+    dest: &Path,
+    timeout: Option<Duration>,
+) -> Result<(), FetchError> {
+    match file_sha256(dest) {
+        Ok(existing) if existing == spec.sha256 => {
+            debug!("{} is already fetched", spec.url);
+            return Ok(());
+        }
+        Ok(_) => warn!(
+            "{} doesn't match its checksum, fetching it again",
+            dest.display()
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(format!(".partial-{}", std::process::id()));
+    let partial = PathBuf::from(partial);
+    let fetched = download(spec, &partial, timeout).and_then(|()| {
+        std::fs::rename(&partial, dest)?;
+        Ok(())
+    });
+    if fetched.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    fetched
+}
+
+/// Downloads `spec.url` to `dest` for [`fetch_and_verify`], failing if the checksum doesn't match.
+fn download(
+    spec: &ArchiveSource,
+    dest: &Path,
+    timeout: Option<Duration>,
+) -> Result<(), FetchError> {
+    info!("fetching {}", spec.url);
+    let write_out = format!(
+        "%{{stderr}}{HTTP_STATUS_MARKER}%{{http_code}}\n\
+         {LAST_MODIFIED_MARKER}%header{{last-modified}}\n"
+    );
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--silent", "--show-error", "--location"])
+        .arg("--write-out")
+        .arg(write_out);
+    if let Some(timeout) = timeout {
+        curl.arg("--max-time")
+            .arg(timeout.as_secs_f64().to_string());
+    }
+    let mut curl = curl
+        .arg(&spec.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // drained alongside the body so curl never blocks on a full stderr pipe
+    let mut stderr = curl.stderr.take().expect("curl stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+
+    let mut body = curl.stdout.take().expect("curl stdout is piped");
+    let mut file = File::create(dest)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = body.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n])?;
+    }
+
+    let status = curl.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let mut http_status = None;
+        let mut message = Vec::new();
+        for line in stderr.lines() {
+            if line.starts_with(LAST_MODIFIED_MARKER) {
+                continue;
+            }
+            match line.strip_prefix(HTTP_STATUS_MARKER) {
+                Some(code) => http_status = code.trim().parse().ok().filter(|&code| code != 0),
+                None => message.push(line.trim_start_matches("curl: ")),
+            }
+        }
+        return Err(FetchError::Download {
+            url: spec.url.clone(),
+            status,
+            http_status,
+            message: message.join("; "),
+        });
+    }
+
+    let actual = to_hex(&hasher.finalize());
+    if actual != spec.sha256 {
+        return Err(FetchError::Mismatch {
+            url: spec.url.clone(),
+            expected: spec.sha256.clone(),
+            actual,
+        });
+    }
+
+    let last_modified = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix(LAST_MODIFIED_MARKER))
+        .and_then(parse_http_date);
+    if let Some(secs) = last_modified {
+        if let Err(e) = file.set_modified(UNIX_EPOCH + Duration::from_secs(secs)) {
+            warn!("could not date {}: {e}", dest.display());
+        }
+    }
+    Ok(())
+}
+
+/// Runs git with `args` in `dir`, returning what it printed on success.
+fn git(url: &str, dir: &Path, args: &[&str]) -> Result<String, FetchError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message: Vec<&str> = stderr
+            .lines()
+            .map(|line| line.trim_start_matches("fatal: "))
+            .filter(|line| !line.is_empty())
+            .collect();
+        return Err(FetchError::Git {
+            url: url.to_owned(),
+            status: output.status,
+            message: message.join("; "),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Checks out `spec.rev` of the repository at `spec.git` into a fresh `dest`, fetching only that
+/// commit when the server allows it and the whole repository when it doesn't.
+///
+/// Fails if the checkout ends up at any other commit, so `rev` must be a full commit hash.
+pub fn fetch_git(spec: &GitSource, dest: &Path) -> Result<(), FetchError> {
+    let url = spec.git.as_str();
+    match std::fs::remove_dir_all(dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    std::fs::create_dir_all(dest)?;
+
+    info!("fetching {url} at {}", spec.rev);
+    git(url, dest, &["init", "--quiet"])?;
+    let shallow = git(
+        url,
+        dest,
+        &["fetch", "--quiet", "--depth", "1", url, &spec.rev],
+    );
+    match shallow {
+        Ok(_) => {
+            git(
+                url,
+                dest,
+                &["checkout", "--quiet", "--detach", "FETCH_HEAD"],
+            )?;
+        }
+        Err(e) if e.is_transient() => return Err(e),
+        Err(e) => {
+            debug!("shallow fetch of {url} failed, fetching everything: {e}");
+            git(url, dest, &["fetch", "--quiet", url])?;
+            git(url, dest, &["checkout", "--quiet", "--detach", &spec.rev])?;
+        }
+    }
+
+    let actual = git(url, dest, &["rev-parse", "HEAD"])?;
+    if !actual.eq_ignore_ascii_case(&spec.rev) {
+        return Err(FetchError::WrongRevision {
+            url: url.to_owned(),
+            expected: spec.rev.clone(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// The committer date of the commit checked out in `dir`, in seconds since the epoch.
+pub fn commit_time(dir: &Path) -> Option<u64> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["log", "-1", "--format=%ct"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
@@ -0,0 +1,234 @@
+//! The dependency graph of a project, for `unpak graph`.
+
+use crate::{ProjectId, SourceProject, Visit};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT, e.g. for `dot -Tsvg`
+    Dot,
+    /// A JSON object of nodes and edges
+    Json,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DepKind {
+    Build,
+    Runtime,
+}
+
+#[derive(Serialize)]
+struct Node<'a> {
+    id: &'a ProjectId,
+    version: Option<String>,
+    /// Not in the registry, so assumed to come from the host.
+    external: bool,
+}
+
+#[derive(Serialize)]
+struct Edge<'a> {
+    from: &'a ProjectId,
+    to: &'a ProjectId,
+    kind: DepKind,
+    /// Part of a dependency cycle.
+    cycle: bool,
+}
+
+/// `root` and everything it transitively depends on, through both `bdeps` and `rdeps`.
+#[derive(Serialize)]
+pub struct Graph<'a> {
+    nodes: Vec<Node<'a>>,
+    edges: Vec<Edge<'a>>,
+}
+
+/// Walks the dependencies of `root` like [`build_project`](crate::build_project) does, but follows
+/// `rdeps` too and records cycles instead of stopping at the first.
+pub fn dependency_graph<'a>(
+    root: &'a SourceProject,
+    registry: &'a HashMap<ProjectId, SourceProject>,
+) -> Graph<'a> {
+    struct Walk<'a> {
+        root: &'a SourceProject,
+        registry: &'a HashMap<ProjectId, SourceProject>,
+        state: HashMap<&'a ProjectId, Visit>,
+        /// The projects being visited, and the edges that led from each to the next.
+        stack: Vec<&'a ProjectId>,
+        edge_stack: Vec<usize>,
+        graph: Graph<'a>,
+    }
+
+    impl<'a> Walk<'a> {
+        fn lookup(&self, id: &ProjectId) -> Option<&'a SourceProject> {
+            if *id == self.root.id {
+                Some(self.root)
+            } else {
+                self.registry.get(id)
+            }
+        }
+
+        fn visit(&mut self, project: &'a SourceProject) {
+            self.state.insert(&project.id, Visit::InProgress);
+            self.stack.push(&project.id);
+            self.graph.nodes.push(Node {
+                id: &project.id,
+                version: project.version.as_ref().map(ToString::to_string),
+                external: false,
+            });
+
+            let deps = (project.bdeps.iter().map(|dep| (dep, DepKind::Build)))
+                .chain(project.rdeps.iter().map(|dep| (dep, DepKind::Runtime)));
+            for (dep, kind) in deps {
+                let edge = self.graph.edges.len();
+                self.graph.edges.push(Edge {
+                    from: &project.id,
+                    to: &dep.id,
+                    kind,
+                    cycle: false,
+                });
+
+                match self.state.get(&dep.id) {
+                    Some(Visit::Done) => {}
+                    Some(Visit::InProgress) => {
+                        // everything from `dep` on the stack back around to it is the cycle
+                        let start = self.stack.iter().position(|id| **id == dep.id).unwrap();
+                        for &on_cycle in &self.edge_stack[start..] {
+                            self.graph.edges[on_cycle].cycle = true;
+                        }
+                        self.graph.edges[edge].cycle = true;
+                    }
+                    None => match self.lookup(&dep.id) {
+                        Some(found) => {
+                            self.edge_stack.push(edge);
+                            self.visit(found);
+                            self.edge_stack.pop();
+                        }
+                        None => {
+                            self.state.insert(&dep.id, Visit::Done);
+                            self.graph.nodes.push(Node {
+                                id: &dep.id,
+                                version: None,
+                                external: true,
+                            });
+                        }
+                    },
+                }
+            }
+
+            self.stack.pop();
+            self.state.insert(&project.id, Visit::Done);
+        }
+    }
+
+    let mut walk = Walk {
+        root,
+        registry,
+        state: HashMap::new(),
+        stack: Vec::new(),
+        edge_stack: Vec::new(),
+        graph: Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        },
+    };
+    walk.visit(root);
+    walk.graph
+}
+
+/// Escapes `s` for use inside a DOT string.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes `s` as a DOT string.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+impl Graph<'_> {
+    /// The graph in Graphviz DOT. Build dependencies are blue, runtime ones green, cycles red
+    /// and projects provided by the host dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n");
+        for node in &self.nodes {
+            // the version goes on a second line of the label
+            let mut label = escape(&node.id.0);
+            if let Some(version) = &node.version {
+                label = format!("{label}\\n{}", escape(version));
+            }
+            let style = if node.external { ", style=dashed" } else { "" };
+            let _ = writeln!(dot, "    {} [label=\"{label}\"{style}];", quote(&node.id.0));
+        }
+        for edge in &self.edges {
+            let color = match (edge.cycle, edge.kind) {
+                (true, _) => "red",
+                (false, DepKind::Build) => "blue",
+                (false, DepKind::Runtime) => "darkgreen",
+            };
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [color={color}];",
+                quote(&edge.from.0),
+                quote(&edge.to.0)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("graphs serialize to JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(id: &str, bdeps: &[&str], rdeps: &[&str]) -> SourceProject {
+        toml::from_str(&format!(
+            "id = \"{id}\"\nbdeps = {bdeps:?}\nrdeps = {rdeps:?}\n\
+             [[build.Cmds]]\nprogram = \"true\"\narguments = []\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn marks_the_edges_of_cycles() {
+        let root = project("app", &["lib"], &["host.libc"]);
+        let registry: HashMap<ProjectId, SourceProject> = [
+            project("lib", &["tool"], &[]),
+            project("tool", &[], &["lib"]),
+        ]
+        .into_iter()
+        .map(|project| (project.id.clone(), project))
+        .collect();
+        let graph = dependency_graph(&root, &registry);
+
+        let cycles: Vec<(&str, &str, bool)> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from.0.as_str(), edge.to.0.as_str(), edge.cycle))
+            .collect();
+        assert_eq!(
+            cycles,
+            [
+                ("app", "lib", false),
+                ("lib", "tool", true),
+                ("tool", "lib", true),
+                ("app", "host.libc", false),
+            ]
+        );
+        let external: Vec<&str> = graph
+            .nodes
+            .iter()
+            .filter(|node| node.external)
+            .map(|node| node.id.0.as_str())
+            .collect();
+        assert_eq!(external, ["host.libc"]);
+        assert!(graph.to_dot().contains("\"tool\" -> \"lib\" [color=red];"));
+    }
+}
@@ -0,0 +1,137 @@
+//! `unpak info`: what a manifest describes, before building it.
+
+use crate::fetch::SourceSpec;
+use crate::{BuildProcess, ProjectId, ProjectRef, SourceProject, SBX_SCRIPT};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+#[derive(Serialize)]
+struct Dep<'a> {
+    #[serde(rename = "ref")]
+    reference: &'a ProjectRef,
+    /// The version found in the registry.
+    version: Option<String>,
+    /// Not in the registry, so assumed to come from the host.
+    external: bool,
+}
+
+/// The metadata of a project along with its resolved dependencies.
+#[derive(Serialize)]
+pub struct Info<'a> {
+    id: &'a ProjectId,
+    version: Option<String>,
+    description: Option<&'a str>,
+    /// The `bdeps`, and everything they need at runtime.
+    build_deps: Vec<Dep<'a>>,
+    /// The `rdeps`, transitively.
+    runtime_deps: Vec<Dep<'a>>,
+    /// The commands the build runs, with presets expanded.
+    build_steps: Vec<String>,
+    source: Option<&'a SourceSpec>,
+}
+
+/// `refs` and, transitively, the `rdeps` of those that are in `registry`, each once.
+fn runtime_closure<'a>(
+    refs: &'a [ProjectRef],
+    registry: &'a HashMap<ProjectId, SourceProject>,
+) -> Vec<Dep<'a>> {
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<&ProjectRef> = refs.iter().collect();
+    let mut deps = Vec::new();
+    while let Some(reference) = queue.pop_front() {
+        if !seen.insert(&reference.id) {
+            continue;
+        }
+        let found = registry.get(&reference.id);
+        deps.push(Dep {
+            reference,
+            version: found.and_then(|dep| dep.version.as_ref().map(ToString::to_string)),
+            external: found.is_none(),
+        });
+        if let Some(found) = found {
+            queue.extend(&found.rdeps);
+        }
+    }
+    deps
+}
+
+/// Collects what `info` shows about `project`, resolving its dependencies against `registry`.
+pub fn project_info<'a>(
+    project: &'a SourceProject,
+    registry: &'a HashMap<ProjectId, SourceProject>,
+) -> Info<'a> {
+    Info {
+        id: &project.id,
+        version: project.version.as_ref().map(ToString::to_string),
+        description: project.description.as_deref(),
+        build_deps: runtime_closure(&project.bdeps, registry),
+        runtime_deps: runtime_closure(&project.rdeps, registry),
+        build_steps: match &project.build {
+            BuildProcess::Script { shell, .. } => {
+                vec![format!("{} {SBX_SCRIPT}", shell.display())]
+            }
+            process => process
+                .commands(project.install_prefix())
+                .unwrap_or_default()
+                .iter()
+                .map(|cmd| match &cmd.arch {
+                    Some(arch) => format!("{cmd} (on {arch} only)"),
+                    None => cmd.to_string(),
+                })
+                .collect(),
+        },
+        source: project.source.as_ref(),
+    }
+}
+
+fn write_deps(report: &mut String, title: &str, deps: &[Dep]) {
+    if deps.is_empty() {
+        let _ = writeln!(report, "{title}: none");
+        return;
+    }
+    let _ = writeln!(report, "{title}:");
+    for dep in deps {
+        let found = match (&dep.version, dep.external) {
+            (_, true) => " (from the host)".to_owned(),
+            (Some(version), false) => format!(" ({version})"),
+            (None, false) => String::new(),
+        };
+        let _ = writeln!(report, "    {}{found}", dep.reference);
+    }
+}
+
+impl Info<'_> {
+    /// A human-readable report.
+    pub fn to_report(&self) -> String {
+        let mut report = match &self.version {
+            Some(version) => format!("{} {version}\n", self.id),
+            None => format!("{}\n", self.id),
+        };
+        if let Some(description) = self.description {
+            let _ = writeln!(report, "{description}");
+        }
+        report.push('\n');
+        match self.source {
+            Some(source) => {
+                let pin = match source {
+                    SourceSpec::Archive(archive) => format!("sha256 {}", archive.sha256),
+                    SourceSpec::Git(git) => format!("commit {}", git.rev),
+                };
+                let _ = writeln!(report, "source: {} ({pin})", source.location());
+            }
+            None => report.push_str("source: the project's directory\n"),
+        }
+        let _ = writeln!(report, "build steps:");
+        for step in &self.build_steps {
+            let _ = writeln!(report, "    {step}");
+        }
+        write_deps(&mut report, "build dependencies", &self.build_deps);
+        write_deps(&mut report, "runtime dependencies", &self.runtime_deps);
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("project info serializes to JSON")
+    }
+}
@@ -0,0 +1,91 @@
+//! `unpak install`: putting what a build staged in place under a root directory.
+//!
+//! The entries installed are those of the [`Package`](crate::state::Package) the last build of a
+//! project recorded, copied from its staging tree. The record then keeps the root, which
+//! [`remove`](crate::remove::remove) deletes them from again, and later builds of the project
+//! leave it alone until then.
+
+use crate::state::{self, EntryKind};
+use crate::{stage, ProjectId};
+use log::debug;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Removes the file or symlink at `path`, if there is one, so something else can take its place.
+fn make_room(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Copies what the last build of `id` staged under `root`, replacing files already there, and
+/// records the package as installed under it. Directories that already exist keep their
+/// permissions. Returns the paths installed, or that would be with `dry_run`.
+pub fn install(id: &ProjectId, root: &Path, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+    let not_built = || io::Error::new(io::ErrorKind::NotFound, "it has not been built");
+    if !id.is_file_name() {
+        return Err(not_built());
+    }
+    let mut package = state::load_package(id).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => not_built(),
+        _ => e,
+    })?;
+    if let Some(installed) = &package.root {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "it is already installed under {}; remove it first",
+                installed.display()
+            ),
+        ));
+    }
+    let root = std::path::absolute(root)?;
+    let staged = stage::staging_dir(id);
+    if !dry_run {
+        std::fs::create_dir_all(&root)?;
+    }
+
+    let mut installed = Vec::with_capacity(package.entries.len());
+    // entries are sorted by path, so each directory is created before what goes in it
+    for entry in &package.entries {
+        if !entry.is_inside_root() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{id} records {}, which is not inside its root",
+                    entry.path.display()
+                ),
+            ));
+        }
+        let to = root.join(&entry.path);
+        installed.push(to.clone());
+        if dry_run {
+            continue;
+        }
+        debug!("installing {}", to.display());
+        let mode = std::fs::Permissions::from_mode(entry.mode);
+        match &entry.kind {
+            EntryKind::Dir => match std::fs::create_dir(&to) {
+                Ok(()) => std::fs::set_permissions(&to, mode)?,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && to.is_dir() => {}
+                Err(e) => return Err(e),
+            },
+            EntryKind::File { .. } => {
+                make_room(&to)?;
+                std::fs::copy(staged.join(&entry.path), &to)?;
+                std::fs::set_permissions(&to, mode)?;
+            }
+            EntryKind::Symlink { target } => {
+                make_room(&to)?;
+                std::os::unix::fs::symlink(target, &to)?;
+            }
+        }
+    }
+    if !dry_run {
+        package.root = Some(root);
+        state::save_installed(&package)?;
+    }
+    Ok(installed)
+}
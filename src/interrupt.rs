@@ -0,0 +1,110 @@
+//! Ctrl-C and `SIGTERM` during a build.
+//!
+//! The first signal kills every running sandbox and stops new builds from starting, so the
+//! build fails the usual way: temporary files are removed as they go out of scope and nothing is
+//! cached. Staging trees left half filled are emptied by the next build of their project, or by
+//! `unpak clean`. A second signal exits right away.
+//!
+//! bwrap's `--die-with-parent` covers unpak itself being killed, when none of this gets to run.
+
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The exit code after an interrupted build, as shells report for `SIGINT`.
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// The pids of the sandboxes currently running.
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn kill(pid: u32) {
+    // SAFETY: kill has no memory safety requirements
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+/// Handles Ctrl-C and `SIGTERM` for the rest of the process.
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_CODE);
+        }
+        warn!("interrupted, stopping the running builds; interrupt again to exit immediately");
+        for &pid in RUNNING.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            kill(pid);
+        }
+    })
+}
+
+/// Whether a build was interrupted.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Registers the sandbox `pid` to be killed on interruption until it is [`untrack`]ed.
+pub fn track(pid: u32) {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner()).push(pid);
+    // the handler may have run before the pid was added
+    if interrupted() {
+        kill(pid);
+    }
+}
+
+/// Forgets `pid` once it has been waited for, before the pid can be reused.
+pub fn untrack(pid: u32) {
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|&running| running != pid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    /// Set in the copy of the test binary that gets interrupted, as the handler is for the
+    /// whole process and ends it.
+    const CHILD: &str = "UNPAK_TEST_INTERRUPTED_CHILD";
+
+    fn interrupt_self() {
+        // SAFETY: raise has no memory safety requirements
+        unsafe {
+            libc::raise(libc::SIGINT);
+        }
+    }
+
+    #[test]
+    fn kills_sandboxes_then_exits_on_a_second_interrupt() {
+        if std::env::var_os(CHILD).is_none() {
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "interrupt::tests::kills_sandboxes_then_exits_on_a_second_interrupt",
+                ])
+                .env(CHILD, "1")
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert_eq!(status.code(), Some(EXIT_CODE));
+            return;
+        }
+
+        install().unwrap();
+        let mut sandbox = Command::new("sleep").arg("60").spawn().unwrap();
+        track(sandbox.id());
+        interrupt_self();
+        let status = sandbox.wait().unwrap();
+        untrack(sandbox.id());
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(interrupted());
+
+        interrupt_self();
+        std::thread::sleep(Duration::from_secs(10));
+        panic!("still running after the second interrupt");
+    }
+}
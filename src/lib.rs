@@ -0,0 +1,4778 @@
+//! unpak, the modern package manager
+//! unpak needs a bootstrap GCC to compile the sandboxed-GCC
+//! |bootstrap GCC and programs| -> |stage1 GCC| -> |stage2 GCC| -> coreutils and build deps, etc
+//! stage1 is cross-compiled by the bootstrapper, and
+//! stage2 is compiled by stage1 to ensure full sandboxing.
+//!
+//! As a library, unpak offers its sandboxes and builds to other tools. [`Bubblewrap`] assembles
+//! a bwrap sandbox out of [`Mount`]s and [`Symlink`]s and runs a program in it.
+//! [`load_manifest`] reads a [`SourceProject`], which [`SourceProject::build`] builds in such
+//! sandboxes, and [`build_project`] builds one along with its dependencies like `unpak build`.
+
+mod audit;
+mod cache;
+pub mod clean;
+pub mod config;
+pub mod doctor;
+mod elf;
+pub mod error;
+pub mod events;
+mod fetch;
+pub mod graph;
+pub mod info;
+pub mod install;
+pub mod interrupt;
+mod lock;
+pub mod progress;
+pub mod registry;
+pub mod remove;
+mod seccomp;
+mod stage;
+pub mod state;
+pub mod summary;
+
+use cache::{cache_key, CacheStore, FsCache};
+use clap::ValueEnum;
+use command_fds::{CommandFdExt, FdMapping};
+use elf::{elf_interpreter, is_elf};
+use error::UnpakError;
+use events::Event;
+use fetch::{fetch_with_retry, FetchError, SourceSpec};
+use lock::LockedProject;
+use log::{debug, info, warn};
+use progress::Progress;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Child, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{io, io::ErrorKind, path::PathBuf, process::Command};
+use summary::{BuildSummary, Outcome};
+
+// com.github.osten.unpak
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+pub struct ProjectId(String);
+
+impl From<&str> for ProjectId {
+    fn from(other: &str) -> Self {
+        ProjectId(other.to_string())
+    }
+}
+
+impl From<String> for ProjectId {
+    fn from(other: String) -> Self {
+        ProjectId(other)
+    }
+}
+
+impl ProjectId {
+    /// Whether the id can name a file or directory of its own: a single path component other
+    /// than `.` and `..`. Staging directories and state records are named by id.
+    pub fn is_file_name(&self) -> bool {
+        !self.0.contains('/')
+            && matches!(
+                Path::new(&self.0).components().next(),
+                Some(std::path::Component::Normal(_))
+            )
+    }
+}
+
+impl fmt::Display for ProjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A dependency on a project, written `id` for any version or `id@req` to constrain it,
+/// e.g. `org.gnu.bash@^5.1`.
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ProjectRef {
+    id: ProjectId,
+    req: VersionReq,
+}
+
+impl ProjectRef {
+    /// Whether a project with `version` satisfies this reference. Unversioned projects only
+    /// satisfy unconstrained references.
+    fn matches(&self, version: Option<&Version>) -> bool {
+        match version {
+            Some(version) => self.req.matches(version),
+            None => self.req == VersionReq::STAR,
+        }
+    }
+}
+
+impl FromStr for ProjectRef {
+    type Err = semver::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, req) = match s.split_once('@') {
+            Some((id, req)) => (id, VersionReq::parse(req)?),
+            None => (s, VersionReq::STAR),
+        };
+        Ok(ProjectRef { id: id.into(), req })
+    }
+}
+
+impl TryFrom<String> for ProjectRef {
+    type Error = semver::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ProjectRef> for String {
+    fn from(project: ProjectRef) -> Self {
+        project.to_string()
+    }
+}
+
+impl fmt::Display for ProjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.req == VersionReq::STAR {
+            write!(f, "{}", self.id)
+        } else {
+            write!(f, "{}@{}", self.id, self.req)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SourceProject {
+    id: ProjectId,
+    #[serde(default)]
+    version: Option<Version>,
+    /// A one-line summary of what the project is.
+    #[serde(default)]
+    description: Option<String>,
+    build: BuildProcess,
+    /// Smoke tests run once the build has succeeded, before its staging tree is recorded, such
+    /// as `/stage/usr/bin/foo --version`. They run in sandboxes like the build's, so they find
+    /// what was installed under [`SBX_STAGE_DIR`]. A check that fails fails the build.
+    #[serde(default)]
+    check: Option<Vec<BuildCmd>>,
+    rdeps: Vec<ProjectRef>,
+    bdeps: Vec<ProjectRef>,
+    source: Option<SourceSpec>,
+    /// The prefix the project is configured for and installed under, `/usr` by default. See
+    /// [`stage`] for how it's staged.
+    #[serde(default)]
+    install_prefix: Option<PathBuf>,
+    /// The project's own directory, which projects without a `source` build in: the manifest's,
+    /// or in the registry the one named after the manifest. See [`registry`].
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+impl SourceProject {
+    pub fn id(&self) -> &ProjectId {
+        &self.id
+    }
+
+    pub fn version(&self) -> Option<&Version> {
+        self.version.as_ref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn install_prefix(&self) -> &Path {
+        self.install_prefix
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_PREFIX))
+    }
+
+    /// The `SOURCE_DATE_EPOCH` to build with: the override in `options`, or else the date the
+    /// source archive was last modified on its server or the date of the source commit, once
+    /// it is fetched.
+    fn source_date_epoch(&self, options: &BuildOptions) -> Option<u64> {
+        if let Some(epoch) = options.source_date_epoch {
+            return Some(epoch);
+        }
+        let source = self.source.as_ref()?;
+        if let SourceSpec::Git(_) = source {
+            return fetch::commit_time(&source.path());
+        }
+        let modified = std::fs::metadata(source.path())
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs())
+    }
+
+    /// Makes a source given as a path rather than a URL, such as `vendor/foo-1.0.tar.gz` or
+    /// `../foo.git`, absolute against [`base_dir`](Self::base_dir), so that a manifest can live
+    /// next to its files wherever unpak is run from.
+    fn resolve_local_sources(&mut self) {
+        let (location, is_archive) = match &mut self.source {
+            Some(SourceSpec::Archive(archive)) => (&mut archive.url, true),
+            Some(SourceSpec::Git(git)) => (&mut git.git, false),
+            None => return,
+        };
+        // URLs, and scp-like git locations such as `git@host:repo`
+        if location.contains(':') {
+            return;
+        }
+        let path = match std::path::absolute(self.base_dir.join(&*location)) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("could not resolve the source path {location}: {e}");
+                return;
+            }
+        };
+        *location = if is_archive {
+            format!("file://{}", path.display())
+        } else {
+            path.display().to_string()
+        };
+    }
+
+    /// Downloads and verifies the project's source archive or checks out its repository, if it
+    /// has either.
+    fn fetch(&self, options: &BuildOptions) -> Result<(), FetchError> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        let dest = source.path();
+        if options.dry_run {
+            options.print(format_args!(
+                "# fetch {} to {}",
+                source.location(),
+                dest.display()
+            ));
+            if let SourceSpec::Archive(_) = source {
+                options.print(format_args!(
+                    "# unpack {} into {}",
+                    dest.display(),
+                    fetch::unpack_dir(&self.id).display()
+                ));
+            }
+            return Ok(());
+        }
+        fetch_with_retry(
+            source,
+            &dest,
+            options.fetch_retries + 1,
+            FETCH_BACKOFF,
+            options.fetch_timeout,
+        )?;
+        match source {
+            SourceSpec::Archive(_) => fetch::unpack(&dest, &fetch::unpack_dir(&self.id)),
+            SourceSpec::Git(_) => Ok(()),
+        }
+    }
+
+    /// Runs every build command in its own sandbox, in order, stopping at the first one that fails.
+    pub fn build(&self, options: &BuildOptions) -> Result<(), BuildError> {
+        self.build_with(&[], options)
+    }
+
+    /// Builds like [`build`](Self::build), with `dep_mounts` in every sandbox, usually the
+    /// outputs of the project's `bdeps` from [`sandbox_mounts_for`].
+    pub fn build_with(
+        &self,
+        dep_mounts: &[Mount],
+        options: &BuildOptions,
+    ) -> Result<(), BuildError> {
+        info!("building project {}...", self.id);
+        let started = Instant::now();
+        let mut timings = Vec::new();
+        match &self.build {
+            BuildProcess::Script { .. } if !options.runs_phase(Phase::Build) => {
+                info!("skipping the build phase of {}", self.id);
+            }
+            BuildProcess::Script { shell, body } => {
+                let cmd = BuildCmd {
+                    program: shell.clone(),
+                    arguments: vec![SBX_SCRIPT.to_string()],
+                    workdir: None,
+                    env: Vec::new(),
+                    timeout: None,
+                    arch: None,
+                };
+
+                // the script lives on the host until the build finishes, and is bound into
+                // a scratch /tmp in the sandbox. Dry runs print it instead, so their output
+                // doesn't depend on temporary paths.
+                let scratch = if options.dry_run {
+                    if !options.print_mounts {
+                        options.print(format_args!("# {SBX_SCRIPT}:"));
+                        for line in body.lines() {
+                            options.print(format_args!("#   {line}"));
+                        }
+                    }
+                    None
+                } else {
+                    let scratch = TempDir::new()
+                        .map(|dir| dir.with_keep(options.keep_temp))
+                        .and_then(|dir| {
+                            std::fs::write(dir.path().join("build.sh"), body)?;
+                            Ok(dir)
+                        })
+                        .map_err(|e| BuildError::new(self, &cmd, BuildFailure::Spawn(e)))?;
+                    Some(scratch)
+                };
+                let script = match &scratch {
+                    Some(scratch) => scratch.path().join("build.sh"),
+                    None => PathBuf::from("build.sh"),
+                };
+                let mut mounts = dep_mounts.to_vec();
+                mounts.extend([
+                    Mount::Tmpfs {
+                        sbx_path: "/tmp".into(),
+                        size: None,
+                    },
+                    Mount::ro_bind(script, SBX_SCRIPT),
+                ]);
+                let cmd_started = Instant::now();
+                self.run_cmd(&cmd, mounts, options)?;
+                timings.push((cmd.to_string(), cmd_started.elapsed()));
+            }
+            process => {
+                let phases = process
+                    .phases(self.install_prefix())
+                    .expect("only scripts have no commands");
+                for (phase, cmds) in &phases {
+                    if !options.runs_phase(*phase) {
+                        info!("skipping the {phase} phase of {}", self.id);
+                        continue;
+                    }
+                    info!("{}: {phase} phase", self.id);
+                    let phase_started = Instant::now();
+                    for cmd in cmds {
+                        if !cmd.runs_on_host() {
+                            info!(
+                                "skipping '{cmd}', which only runs on {}",
+                                cmd.arch.as_deref().unwrap_or_default()
+                            );
+                            continue;
+                        }
+                        let cmd_started = Instant::now();
+                        self.run_cmd(cmd, dep_mounts.to_vec(), options)?;
+                        timings.push((cmd.to_string(), cmd_started.elapsed()));
+                    }
+                    debug!(
+                        "{}: {phase} phase done in {:.1}s",
+                        self.id,
+                        phase_started.elapsed().as_secs_f64()
+                    );
+                }
+            }
+        }
+
+        match &self.check {
+            Some(_) if options.no_check => info!("skipping the checks of {}", self.id),
+            Some(_) if !options.runs_every_phase() => {
+                info!("skipping the checks of {}, as not every phase ran", self.id);
+            }
+            Some(checks) => {
+                info!("{}: checking", self.id);
+                for cmd in checks {
+                    if !cmd.runs_on_host() {
+                        info!(
+                            "skipping '{cmd}', which only runs on {}",
+                            cmd.arch.as_deref().unwrap_or_default()
+                        );
+                        continue;
+                    }
+                    let cmd_started = Instant::now();
+                    self.run_cmd(cmd, dep_mounts.to_vec(), options)?;
+                    timings.push((cmd.to_string(), cmd_started.elapsed()));
+                }
+            }
+            None => {}
+        }
+
+        let total = started.elapsed();
+        info!("built project {} in {:.1}s", self.id, total.as_secs_f64());
+        if options.timings && !options.dry_run {
+            print_timings(&self.id, timings, total, options);
+        }
+        Ok(())
+    }
+
+    fn run_cmd(
+        &self,
+        cmd: &BuildCmd,
+        mounts: Vec<Mount>,
+        options: &BuildOptions,
+    ) -> Result<(), BuildError> {
+        let sandbox = self
+            .sandbox_for(cmd, mounts, options)
+            .map_err(|e| BuildError::new(self, cmd, BuildFailure::Spawn(e)))?
+            .with_pipe_output(!options.raw_output)
+            // without a terminal of their own, builds don't need job control
+            .with_new_session(!options.raw_output);
+        if options.dry_run {
+            sandbox.validate();
+            if options.print_mounts {
+                options.print(format_args!("# sandbox of '{cmd}':"));
+                for line in sandbox.mount_report().lines() {
+                    options.print(format_args!("{line}"));
+                }
+            } else {
+                options.print(format_args!("{}", sandbox.shell_command()));
+            }
+            return Ok(());
+        }
+
+        let timeout = cmd.timeout.or(options.build_timeout);
+        info!("executing '{cmd}'");
+        events::record(&Event::CommandStarted {
+            project: &self.id,
+            command: cmd.to_string(),
+        });
+        let prefix = format!(
+            "[{}:{}] ",
+            self.id,
+            cmd.program
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let bwrap = sandbox.bwrap();
+        let status = sandbox
+            .spawn()
+            .and_then(|mut child| {
+                interrupt::track(child.id());
+                let forwarders = [
+                    child
+                        .stdout
+                        .take()
+                        .map(|out| forward_prefixed(out, &prefix, options.output_to_stderr)),
+                    child
+                        .stderr
+                        .take()
+                        .map(|err| forward_prefixed(err, &prefix, true)),
+                ];
+                let status = wait_timeout(&mut child, timeout);
+                interrupt::untrack(child.id());
+                // the output pipes close once the sandbox is gone, so whatever it printed before
+                // being killed still gets through
+                for forwarder in forwarders.into_iter().flatten() {
+                    let _ = forwarder.join();
+                }
+                status
+            })
+            .map_err(|e| BuildError::new(self, cmd, BuildFailure::Spawn(e)))?;
+        events::record(&Event::CommandFinished {
+            project: &self.id,
+            command: cmd.to_string(),
+            exit_code: status.and_then(|status| status.code()),
+            signal: status.and_then(|status| status.signal()),
+        });
+
+        if interrupt::interrupted() {
+            return Err(BuildError::new(self, cmd, BuildFailure::Interrupted));
+        }
+        let Some(status) = status else {
+            let timeout = timeout.expect("only commands with a timeout time out");
+            return Err(BuildError::new(self, cmd, BuildFailure::Timeout(timeout)));
+        };
+        if let Some(signal) = status.signal() {
+            return Err(BuildError::new(self, cmd, BuildFailure::Signal(signal)));
+        }
+        if let Some(e) = userns_failure(&bwrap, status) {
+            return Err(BuildError::new(self, cmd, BuildFailure::Spawn(e)));
+        }
+        if !status.success() {
+            return Err(BuildError::new(self, cmd, BuildFailure::Exit(status)));
+        }
+        Ok(())
+    }
+
+    /// Checks what deserializing the manifest can't: that the project has an id and something to
+    /// build, doesn't depend on itself, and doesn't name absolute programs the host lacks.
+    /// Returns every problem found, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        if self.id.0.trim().is_empty() {
+            errors.push(ValidationError::EmptyId);
+        }
+        for (list, deps) in [("bdeps", &self.bdeps), ("rdeps", &self.rdeps)] {
+            if deps.iter().any(|dep| dep.id == self.id) {
+                errors.push(ValidationError::DependsOnItself { list });
+            }
+        }
+        let programs = match &self.build {
+            BuildProcess::Script { shell, .. } => vec![shell.clone()],
+            process => process
+                .commands(self.install_prefix())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|cmd| cmd.program)
+                .collect(),
+        };
+        if programs.is_empty() {
+            errors.push(ValidationError::NothingToBuild);
+        }
+        let checks = self.check.iter().flatten().map(|cmd| cmd.program.clone());
+        for program in programs.into_iter().chain(checks) {
+            if program.is_absolute() && !program.exists() {
+                errors.push(ValidationError::MissingProgram(program));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The source tree builds run in: the checkout of a git source, the unpacked archive of an
+    /// archive source, or else the project's own directory.
+    fn build_dir(&self) -> io::Result<PathBuf> {
+        match &self.source {
+            Some(source @ SourceSpec::Git(_)) => Ok(source.path()),
+            Some(SourceSpec::Archive(_)) => fetch::source_root(&fetch::unpack_dir(&self.id)),
+            None => std::fs::canonicalize(&self.base_dir),
+        }
+    }
+
+    /// The environment `cmd` runs in, in the order it is set: what unpak sets for every build,
+    /// then the command's own `env`.
+    fn build_env(&self, cmd: &BuildCmd, options: &BuildOptions) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("UNPAK_DESTDIR".to_owned(), SBX_STAGE_DIR.to_owned()),
+            (
+                "UNPAK_PREFIX".to_owned(),
+                self.install_prefix().display().to_string(),
+            ),
+        ];
+        // commands may still set their own
+        if let Some(epoch) = self.source_date_epoch(options) {
+            env.push(("SOURCE_DATE_EPOCH".to_owned(), epoch.to_string()));
+        }
+        env.extend(cmd.env.iter().cloned());
+        env
+    }
+
+    /// Prepares a sandbox that runs `cmd` with the project's source tree mounted writable at
+    /// [`SBX_BUILD_DIR`], in addition to `mounts`. The source tree is the checkout or unpacked
+    /// archive of a fetched source, or else the project's own directory.
+    ///
+    /// Variables in the program and arguments of `cmd` are expanded first, as by
+    /// [`expand_vars`], from the environment of the command rather than unpak's own.
+    ///
+    /// Programs given by name or absolute path are run from `mounts` when one of them, such as
+    /// the staged files of a build dependency, puts the program in `/usr/bin` or at that path.
+    /// Otherwise they are looked up on the host and mounted into `/usr/bin`. Relative paths such
+    /// as `./configure` are run from the source tree instead.
+    ///
+    /// With [`BuildOptions::allowed_paths`] set, every host path bound must be under one of them,
+    /// the source tree or [`DEFAULT_ALLOWED_PATHS`].
+    fn sandbox_for(
+        &self,
+        cmd: &BuildCmd,
+        mut mounts: Vec<Mount>,
+        options: &BuildOptions,
+    ) -> io::Result<Bubblewrap> {
+        let base_dir = self.build_dir()?;
+        mounts.push(Mount::rw_bind(base_dir.clone(), SBX_BUILD_DIR));
+        mounts.push(Mount::rw_bind(stage::staging_dir(&self.id), SBX_STAGE_DIR));
+
+        let env = self.build_env(cmd, options);
+        let expand = |text: &str| {
+            expand_vars(text, &env, options.allow_unset_vars)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("in '{cmd}': {e}")))
+        };
+        let cmd_program = PathBuf::from(expand(&cmd.program.to_string_lossy())?);
+        let arguments = cmd
+            .arguments
+            .iter()
+            .map(|argument| expand(argument))
+            .collect::<io::Result<Vec<String>>>()?;
+
+        let is_relative = cmd_program.is_relative() && cmd_program.components().count() > 1;
+        let mut interpreter = PathBuf::from(INTERPRETER_HOST);
+        // build dependencies are installed where the command looks, so theirs come first
+        let installed = if cmd_program.is_absolute() {
+            cmd_program.clone()
+        } else {
+            Path::new(FHS_EXE).join(&cmd_program)
+        };
+        let from_dep = mounts.iter().find_map(|mount| match mount {
+            Mount::Fs {
+                host_path,
+                sbx_path,
+                ..
+            } if sbx_path.0 == installed => Some(host_path.0.clone()),
+            _ => None,
+        });
+        let program = if is_relative {
+            cmd_program
+        } else if let Some(host) = from_dep {
+            interpreter = interpreter_for(&host);
+            installed
+        } else {
+            let host = which::which(&cmd_program).map_err(|e| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{}: {e}", cmd_program.display()),
+                )
+            })?;
+            let program = Path::new(FHS_EXE).join(host.file_name().unwrap_or_default());
+            interpreter = interpreter_for(&host);
+            mounts.push(Mount::ro_bind(host, program.clone()));
+            program
+        };
+
+        let chdir = match &cmd.workdir {
+            Some(workdir) => Path::new(SBX_BUILD_DIR).join(workdir),
+            None => PathBuf::from(SBX_BUILD_DIR),
+        };
+
+        let mut profile = Profile::minimal().with_user(SBX_UID, SBX_GID);
+        if options.no_std_symlinks {
+            profile = profile.without_std_symlinks();
+        }
+        let mut sandbox = base_sandbox(&profile, &interpreter, mounts)?
+            .with_program(program)
+            .with_args(arguments)
+            .with_chdir(chdir)
+            .with_inherit_env(false)
+            .with_unshare_net(!options.share_net)
+            // the build runs as pid 1 of its own namespace, so everything it started dies with it
+            .with_unshare_pid(true)
+            .with_die_with_parent(true)
+            .with_cap_drop_all()
+            .with_hostname(SBX_HOSTNAME)
+            .with_uid(SBX_UID)
+            .with_gid(SBX_GID);
+        match seccomp::default_filter_path()? {
+            Some(filter) => sandbox = sandbox.with_seccomp(filter),
+            None => warn!(
+                "no default seccomp filter for this architecture, syscalls are not restricted"
+            ),
+        }
+        for (id, value) in env {
+            sandbox.add_envvar(id.into(), value.into());
+        }
+
+        if let Some(allowed) = &options.allowed_paths {
+            let mut allowed = allowed.clone();
+            allowed.push(base_dir);
+            check_allowed_paths(&sandbox.mounts, &allowed)?;
+        }
+        Ok(sandbox)
+    }
+}
+
+/// Why the variables in a build command couldn't be expanded.
+#[derive(Debug)]
+pub enum ExpandError {
+    /// Names a variable the command's environment doesn't set.
+    Unset(String),
+    /// A `${` without its `}`.
+    Unterminated,
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpandError::Unset(name) => write!(f, "${name} is not set"),
+            ExpandError::Unterminated => f.write_str("a ${ is missing its }"),
+        }
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+/// Replaces `${NAME}` and `$NAME` in `text` with the value of `NAME` in `env`, the last one if
+/// it is set more than once, and `$$` with `$`. A `$` followed by anything else stays as it is,
+/// so `$(nproc)` and `$1` are left to a shell.
+///
+/// Variables `env` doesn't set are an error, unless `allow_unset` lets them expand to nothing.
+pub fn expand_vars(
+    text: &str,
+    env: &[(String, String)],
+    allow_unset: bool,
+) -> Result<String, ExpandError> {
+    let is_name_char = |c: char| c == '_' || c.is_ascii_alphanumeric();
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, tail) = match after.chars().next() {
+            Some('$') => {
+                expanded.push('$');
+                rest = &after[1..];
+                continue;
+            }
+            Some('{') => {
+                let end = after.find('}').ok_or(ExpandError::Unterminated)?;
+                (&after[1..end], &after[end + 1..])
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+            _ => {
+                expanded.push('$');
+                rest = after;
+                continue;
+            }
+        };
+        match env.iter().rev().find(|(id, _)| id == name) {
+            Some((_, value)) => expanded.push_str(value),
+            None if allow_unset => debug!("expanding unset ${name} to nothing"),
+            None => return Err(ExpandError::Unset(name.to_owned())),
+        }
+        rest = tail;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[derive(Debug)]
+pub enum BuildFailure {
+    /// The command could not be started or waited on.
+    Spawn(io::Error),
+    /// The command ran but exited unsuccessfully.
+    Exit(ExitStatus),
+    /// The command was killed by this signal, other than by unpak itself.
+    Signal(i32),
+    /// The command was killed after running longer than allowed.
+    Timeout(Duration),
+    /// The command was killed because unpak was interrupted.
+    Interrupted,
+}
+
+impl BuildFailure {
+    /// Whether running the command again might succeed: it was killed from outside, as the OOM
+    /// killer does, rather than crashing or failing on its own.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            BuildFailure::Signal(libc::SIGKILL | libc::SIGTERM | libc::SIGHUP)
+        )
+    }
+}
+
+/// The conventional name of `signal`, such as `SIGSEGV`.
+fn signal_name(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// How a sandbox ended, as in "exited with code 2" or "killed by signal SIGSEGV (11)".
+fn describe_status(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with code {code}"),
+        (None, Some(signal)) => match signal_name(signal) {
+            Some(name) => format!("killed by signal {name} ({signal})"),
+            None => format!("killed by signal {signal}"),
+        },
+        (None, None) => status.to_string(),
+    }
+}
+
+/// A build command of a project that failed to run to completion.
+#[derive(Debug)]
+pub struct BuildError {
+    project: ProjectId,
+    program: PathBuf,
+    command: String,
+    failure: BuildFailure,
+}
+
+impl BuildError {
+    pub fn failure(&self) -> &BuildFailure {
+        &self.failure
+    }
+
+    fn new(project: &SourceProject, cmd: &BuildCmd, failure: BuildFailure) -> Self {
+        BuildError {
+            project: project.id.clone(),
+            program: cmd.program.clone(),
+            command: cmd.to_string(),
+            failure,
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let BuildError {
+            project,
+            program,
+            command,
+            ..
+        } = self;
+        match &self.failure {
+            BuildFailure::Spawn(e) => write!(
+                f,
+                "failed to run '{}' for project {project}: {e}",
+                program.display()
+            ),
+            BuildFailure::Exit(status) => match status.code() {
+                Some(code) => write!(
+                    f,
+                    "command '{command}' in project {project} exited with status {code}"
+                ),
+                None => write!(f, "command '{command}' in project {project} {status}"),
+            },
+            BuildFailure::Signal(signal) => write!(
+                f,
+                "command '{command}' in project {project} was {}",
+                describe_status(ExitStatus::from_raw(*signal))
+            ),
+            BuildFailure::Timeout(timeout) => write!(
+                f,
+                "command '{command}' in project {project} was killed after {}s",
+                timeout.as_secs_f64()
+            ),
+            BuildFailure::Interrupted => {
+                write!(
+                    f,
+                    "command '{command}' in project {project} was interrupted"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.failure {
+            BuildFailure::Spawn(e) => Some(e),
+            BuildFailure::Exit(_)
+            | BuildFailure::Signal(_)
+            | BuildFailure::Timeout(_)
+            | BuildFailure::Interrupted => None,
+        }
+    }
+}
+
+/// Where a project's source tree is mounted in its build sandbox.
+const SBX_BUILD_DIR: &str = "/build";
+/// Where a build's staging tree is bound, exported to it as `$UNPAK_DESTDIR`.
+const SBX_STAGE_DIR: &str = "/stage";
+/// The install prefix of projects that don't set their own.
+const DEFAULT_PREFIX: &str = "/usr";
+/// Where the body of a [`BuildProcess::Script`] is mounted in its build sandbox.
+const SBX_SCRIPT: &str = "/tmp/unpak-build.sh";
+/// The fixed identity sandboxed programs run as.
+const SBX_UID: u32 = 1000;
+const SBX_GID: u32 = 1000;
+
+/// The descriptor bwrap reads the seccomp filter from.
+const SECCOMP_FD: i32 = 3;
+/// The first descriptor bwrap reads the contents of [`Mount::File`]s and [`Mount::BindData`]s
+/// from, one after another in mount order.
+const FILE_FD_BASE: i32 = 4;
+
+/// The hostname seen inside the sandbox.
+const SBX_HOSTNAME: &str = "unpak-sandbox";
+const INTERPRETER_HOST: &str = "/lib64/ld-linux-x86-64.so.2";
+const SBX_LD_LINUX: &str = "/usr/lib/ld-linux-x86-64.so.2";
+
+/// A dependency cycle between projects, listed in dependency order.
+#[derive(Debug)]
+pub struct CycleError {
+    cycle: Vec<ProjectId>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("dependency cycle: ")?;
+        for (i, id) in self.cycle.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{id}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+#[derive(Debug)]
+pub enum PlanError {
+    Cycle(CycleError),
+    /// `dependent` requires a version of `dep` that the registry doesn't have.
+    Unsatisfied {
+        dependent: ProjectId,
+        dep: ProjectRef,
+        found: Option<Version>,
+    },
+    /// The lockfile pins a project that isn't available at that version anymore.
+    Stale(LockedProject),
+    /// `dependent` now requires `dep`, which the lockfile doesn't pin at a version satisfying it.
+    Unlocked {
+        dependent: ProjectId,
+        dep: ProjectRef,
+    },
+    /// Asked to build a project by an id the registry doesn't have.
+    Unknown(ProjectId),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Cycle(e) => e.fmt(f),
+            PlanError::Unsatisfied {
+                dependent,
+                dep,
+                found,
+            } => {
+                write!(f, "{dependent} requires {dep}, but ")?;
+                match found {
+                    Some(version) => write!(f, "version {version} was found"),
+                    None => f.write_str("an unversioned project was found"),
+                }
+            }
+            PlanError::Stale(locked) => {
+                write!(f, "the lockfile pins {}", locked.id)?;
+                if let Some(version) = &locked.version {
+                    write!(f, " {version}")?;
+                }
+                f.write_str(", which is no longer available; rerun with --update")
+            }
+            PlanError::Unlocked { dependent, dep } => write!(
+                f,
+                "{dependent} requires {dep}, which the lockfile doesn't pin; rerun with --update"
+            ),
+            PlanError::Unknown(id) => write!(
+                f,
+                "{id} is neither a manifest file nor a project in the registry"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlanError::Cycle(e) => Some(e),
+            PlanError::Unsatisfied { .. }
+            | PlanError::Stale(_)
+            | PlanError::Unlocked { .. }
+            | PlanError::Unknown(_) => None,
+        }
+    }
+}
+
+impl From<CycleError> for PlanError {
+    fn from(e: CycleError) -> Self {
+        PlanError::Cycle(e)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+/// Orders `root` and its transitive `bdeps` so that every project comes after its dependencies.
+///
+/// Dependencies that aren't in `registry` are assumed to be provided by the host and are left out,
+/// while ones whose version doesn't satisfy the dependent's requirement are an error.
+fn build_plan(
+    root: &SourceProject,
+    registry: &HashMap<ProjectId, SourceProject>,
+) -> Result<Vec<ProjectId>, PlanError> {
+    fn visit<'a>(
+        project: &'a SourceProject,
+        lookup: &impl Fn(&ProjectId) -> Option<&'a SourceProject>,
+        state: &mut HashMap<&'a ProjectId, Visit>,
+        stack: &mut Vec<&'a ProjectId>,
+        plan: &mut Vec<ProjectId>,
+    ) -> Result<(), PlanError> {
+        match state.get(&project.id) {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::InProgress) => {
+                let start = stack.iter().position(|id| **id == project.id).unwrap();
+                let mut cycle: Vec<ProjectId> =
+                    stack[start..].iter().map(|&id| id.clone()).collect();
+                cycle.push(project.id.clone());
+                return Err(CycleError { cycle }.into());
+            }
+            None => {}
+        }
+
+        state.insert(&project.id, Visit::InProgress);
+        stack.push(&project.id);
+        for dep in &project.bdeps {
+            let Some(found) = lookup(&dep.id) else {
+                continue;
+            };
+            if !dep.matches(found.version.as_ref()) {
+                return Err(PlanError::Unsatisfied {
+                    dependent: project.id.clone(),
+                    dep: dep.clone(),
+                    found: found.version.clone(),
+                });
+            }
+            visit(found, lookup, state, stack, plan)?;
+        }
+        stack.pop();
+        state.insert(&project.id, Visit::Done);
+        plan.push(project.id.clone());
+        Ok(())
+    }
+
+    let lookup = |id: &ProjectId| {
+        if *id == root.id {
+            Some(root)
+        } else {
+            registry.get(id)
+        }
+    };
+
+    let mut plan = Vec::new();
+    let mut state = HashMap::new();
+    visit(root, &lookup, &mut state, &mut Vec::new(), &mut plan)?;
+    Ok(plan)
+}
+
+/// The build plan pinned by a lockfile, checking that every locked project is still available at
+/// its locked version, and that the lockfile pins every build dependency the projects now have.
+fn locked_plan(
+    root: &SourceProject,
+    registry: &HashMap<ProjectId, SourceProject>,
+    locked: Vec<LockedProject>,
+) -> Result<Vec<ProjectId>, PlanError> {
+    if !locked.iter().any(|project| project.id == root.id) {
+        return Err(PlanError::Stale(LockedProject {
+            id: root.id.clone(),
+            version: root.version.clone(),
+        }));
+    }
+    let lookup = |id: &ProjectId| {
+        if *id == root.id {
+            Some(root)
+        } else {
+            registry.get(id)
+        }
+    };
+    let mut pinned = Vec::with_capacity(locked.len());
+    for project in locked {
+        match lookup(&project.id) {
+            Some(found) if found.version == project.version => pinned.push(found),
+            _ => return Err(PlanError::Stale(project)),
+        }
+    }
+    for project in &pinned {
+        // those that aren't in the registry come from the host, as in build_plan
+        let deps = project.bdeps.iter().filter(|dep| lookup(&dep.id).is_some());
+        for dep in deps {
+            let locked = pinned.iter().find(|pinned| pinned.id == dep.id);
+            if !locked.is_some_and(|locked| dep.matches(locked.version.as_ref())) {
+                return Err(PlanError::Unlocked {
+                    dependent: project.id.clone(),
+                    dep: dep.clone(),
+                });
+            }
+        }
+    }
+    Ok(pinned
+        .into_iter()
+        .map(|project| project.id.clone())
+        .collect())
+}
+
+/// Why a project of a build plan didn't get built.
+#[derive(Debug)]
+pub enum ProjectFailure {
+    Fetch(FetchError),
+    Build(BuildError),
+    Cache(io::Error),
+    Stage(io::Error),
+    /// What the build staged couldn't be recorded as a [`state::Package`].
+    Package(io::Error),
+    /// With [`BuildOptions::audit`], the build changed these files outside its staging tree.
+    Unhermetic(Vec<audit::Change>),
+    /// With [`BuildOptions::audit`], the source tree couldn't be snapshotted.
+    Audit(io::Error),
+}
+
+impl fmt::Display for ProjectFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectFailure::Fetch(e) => e.fmt(f),
+            ProjectFailure::Build(e) => e.fmt(f),
+            ProjectFailure::Cache(e) => write!(f, "build cache: {e}"),
+            ProjectFailure::Stage(e) => write!(f, "staging tree: {e}"),
+            ProjectFailure::Package(e) => write!(f, "could not record the staged files: {e}"),
+            ProjectFailure::Unhermetic(changes) => {
+                write!(f, "the build wrote outside its staging tree:")?;
+                for change in changes {
+                    write!(f, "\n    {change}")?;
+                }
+                Ok(())
+            }
+            ProjectFailure::Audit(e) => write!(f, "could not audit the build: {e}"),
+        }
+    }
+}
+
+/// The projects of a build plan that failed, and those skipped because a dependency failed.
+#[derive(Debug)]
+pub struct BuildAllError {
+    failed: Vec<(ProjectId, ProjectFailure)>,
+    skipped: Vec<ProjectId>,
+    /// Whether unpak was interrupted, which stops projects from starting even when none failed.
+    interrupted: bool,
+    summary: BuildSummary,
+}
+
+impl BuildAllError {
+    /// What happened to every project of the plan, including those that succeeded.
+    pub fn summary(&self) -> &BuildSummary {
+        &self.summary
+    }
+}
+
+impl fmt::Display for BuildAllError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(id, failure)| format!("{id}: {failure}"))
+            .collect();
+        if self.interrupted && self.failed.is_empty() {
+            lines.push("the build was interrupted".to_owned());
+        }
+        if !self.skipped.is_empty() {
+            let skipped: Vec<String> = self.skipped.iter().map(ToString::to_string).collect();
+            let reason = if self.interrupted {
+                "the interruption"
+            } else {
+                "failed dependencies"
+            };
+            lines.push(format!("skipped due to {reason}: {}", skipped.join(", ")));
+        }
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+impl std::error::Error for BuildAllError {}
+
+/// Builds `project` against `deps`, the ids and cache keys of its build dependencies, into a
+/// fresh staging tree, or restores the staged tree from `cache` if that has already been done
+/// with the same inputs. Returns the cache key along with what happened.
+///
+/// Dry runs never touch the staging tree or the cache, and have no key. Builds that skip phases
+/// or checks never touch the cache.
+fn build_cached(
+    project: &SourceProject,
+    deps: &[(ProjectId, String)],
+    dep_mounts: &[Mount],
+    cache: &dyn CacheStore,
+    options: &BuildOptions,
+) -> Result<(Outcome, Option<String>), ProjectFailure> {
+    if options.dry_run {
+        project.fetch(options).map_err(ProjectFailure::Fetch)?;
+        project
+            .build_with(dep_mounts, options)
+            .map_err(ProjectFailure::Build)?;
+        return Ok((Outcome::Planned, None));
+    }
+
+    // fetched sources are pinned by their checksum or rev, but a project without one builds
+    // whatever is in its directory
+    let tree = match project.source {
+        Some(_) => None,
+        None => Some(cache::source_tree_hash(&project.base_dir).map_err(ProjectFailure::Cache)?),
+    };
+    let key = cache_key(project, deps, tree.as_deref(), options);
+    let staged = stage::reset(&project.id).map_err(ProjectFailure::Stage)?;
+    // a build with phases or checks skipped isn't the build the key stands for
+    let cacheable = options.runs_every_phase() && !(options.no_check && project.check.is_some());
+    let restored = cacheable
+        && cache
+            .restore(&key, &staged)
+            .map_err(ProjectFailure::Cache)?;
+    let outcome = if restored {
+        info!(
+            "{} is up to date, restored from the build cache",
+            project.id
+        );
+        Outcome::Cached
+    } else {
+        project.fetch(options).map_err(ProjectFailure::Fetch)?;
+        let before = options
+            .audit
+            .then(|| project.build_dir().and_then(|dir| audit::snapshot(&dir)))
+            .transpose()
+            .map_err(ProjectFailure::Audit)?;
+        project.build_with(dep_mounts, options).map_err(|e| {
+            // the staging tree stays as the build left it until the project is built again
+            if options.keep_temp {
+                info!(
+                    "kept the staging tree of {} at {}",
+                    project.id,
+                    staged.display()
+                );
+            }
+            ProjectFailure::Build(e)
+        })?;
+        if let Some(before) = before {
+            let after = before.retake().map_err(ProjectFailure::Audit)?;
+            let changes = before.changes(&after);
+            if !changes.is_empty() {
+                return Err(ProjectFailure::Unhermetic(changes));
+            }
+        }
+        if cacheable {
+            debug!("caching {} as {key}", project.id);
+            cache.store(&key, &staged).map_err(ProjectFailure::Cache)?;
+        }
+        Outcome::Built
+    };
+
+    stage::list_staged(&staged)
+        .and_then(|files| stage::record_staged(&project.id, &files))
+        .map_err(ProjectFailure::Stage)?;
+    let package = state::Package {
+        id: project.id.clone(),
+        version: project.version.clone(),
+        root: None,
+        entries: stage::scan(&staged).map_err(ProjectFailure::Package)?,
+    };
+    state::save_package(&package).map_err(ProjectFailure::Package)?;
+    Ok((outcome, Some(key)))
+}
+
+/// Read-only binds of everything the build dependencies of `project` staged, at the paths they
+/// were staged for, so a build finds the tools it depends on installed where it expects them.
+///
+/// Build dependencies are followed transitively through the `bdeps` of those in `registry`;
+/// the others are expected to come from the host. When two of them staged the same path, the one
+/// closest to `project` wins. Dependencies that haven't been built yet are skipped with a warning.
+pub fn sandbox_mounts_for(
+    project: &SourceProject,
+    registry: &HashMap<ProjectId, SourceProject>,
+) -> Vec<Mount> {
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<&ProjectId> = project.bdeps.iter().map(|dep| &dep.id).collect();
+    let mut placed = HashSet::new();
+    let mut mounts = Vec::new();
+    while let Some(id) = queue.pop_front() {
+        let Some(dep) = registry.get(id) else {
+            continue;
+        };
+        if !seen.insert(id) {
+            continue;
+        }
+        queue.extend(dep.bdeps.iter().map(|dep| &dep.id));
+
+        let files = match stage::read_staged(id) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!(
+                    "not mounting the outputs of {id} into the build of {}: {e}",
+                    project.id
+                );
+                continue;
+            }
+        };
+        let staged = stage::staging_dir(id);
+        for file in files {
+            let sbx_path = Path::new("/").join(&file);
+            if placed.insert(sbx_path.clone()) {
+                mounts.push(Mount::ro_bind(staged.join(&file), sbx_path));
+            }
+        }
+    }
+    mounts
+}
+
+/// How often the progress line is redrawn while projects build.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fetches and builds every project of `plan`, running up to `jobs` builds at once.
+///
+/// A project starts once all of its `bdeps` in the plan have been built. When a build fails, the
+/// projects depending on it are never started, but builds already running are left to finish.
+/// Once unpak is interrupted, no more projects start, and the build fails even if none did.
+fn build_all(
+    plan: &[ProjectId],
+    registry: &HashMap<ProjectId, SourceProject>,
+    jobs: usize,
+    cache: &(dyn CacheStore + Sync),
+    options: &BuildOptions,
+) -> Result<BuildSummary, BuildAllError> {
+    let project = |id: &ProjectId| &registry[id];
+    let mut deps_of: HashMap<&ProjectId, Vec<ProjectId>> = HashMap::new();
+    let mut unmet: HashMap<&ProjectId, usize> = HashMap::new();
+    let mut dependents: HashMap<&ProjectId, Vec<&ProjectId>> = HashMap::new();
+    for id in plan {
+        let deps: HashSet<&ProjectId> = project(id)
+            .bdeps
+            .iter()
+            .map(|dep| &dep.id)
+            .filter(|dep| plan.contains(dep))
+            .collect();
+        unmet.insert(id, deps.len());
+        for &dep in &deps {
+            dependents.entry(dep).or_default().push(id);
+        }
+        deps_of.insert(id, deps.into_iter().cloned().collect());
+    }
+
+    let mut ready: VecDeque<&ProjectId> = plan.iter().filter(|id| unmet[id] == 0).collect();
+    let mut failed = Vec::new();
+    let mut finished = HashSet::new();
+    // the cache keys of the projects built so far, which those depending on them are keyed on
+    let mut keys: HashMap<&ProjectId, String> = HashMap::new();
+    let mut summary = BuildSummary::default();
+    let mut progress = Progress::new(plan.len());
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let mut running = 0;
+        loop {
+            while running < jobs.max(1) && !interrupt::interrupted() {
+                let Some(id) = ready.pop_front() else {
+                    break;
+                };
+                progress.started(id);
+                let tx = tx.clone();
+                let deps: Vec<(ProjectId, String)> = deps_of[id]
+                    .iter()
+                    .filter_map(|dep| Some((dep.clone(), keys.get(dep)?.clone())))
+                    .collect();
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let dep_mounts = sandbox_mounts_for(project(id), registry);
+                    let result = build_cached(project(id), &deps, &dep_mounts, cache, options);
+                    tx.send((id, result, started.elapsed()))
+                        .expect("scheduler outlives its builds");
+                });
+                running += 1;
+            }
+            if running == 0 {
+                break;
+            }
+
+            let (id, result, elapsed) = match rx.recv_timeout(PROGRESS_INTERVAL) {
+                Ok(finished) => finished,
+                Err(_) => {
+                    progress.redraw();
+                    continue;
+                }
+            };
+            running -= 1;
+            progress.finished(id);
+            finished.insert(id);
+            summary.record(id, result.as_ref().map(|&(outcome, _)| outcome), elapsed);
+            match result {
+                Ok((_, key)) => {
+                    if let Some(key) = key {
+                        keys.insert(id, key);
+                    }
+                    for &dependent in dependents.get(id).into_iter().flatten() {
+                        let count = unmet.get_mut(dependent).unwrap();
+                        *count -= 1;
+                        if *count == 0 {
+                            ready.push_back(dependent);
+                        }
+                    }
+                }
+                Err(failure) => {
+                    if let ProjectFailure::Build(e) = &failure {
+                        if e.failure.is_retryable() {
+                            warn!("{id} was killed from outside, perhaps out of memory; it may build if retried");
+                        }
+                    }
+                    failed.push((id.clone(), failure));
+                }
+            }
+        }
+    });
+
+    drop(progress);
+    if !options.dry_run {
+        info!(
+            "up to date: {}, rebuilt: {}",
+            summary.count(Outcome::Cached),
+            summary.count(Outcome::Built)
+        );
+    }
+
+    let skipped: Vec<ProjectId> = plan
+        .iter()
+        .filter(|id| !finished.contains(id))
+        .cloned()
+        .collect();
+    for id in &skipped {
+        summary.record_skipped(id);
+    }
+    let interrupted = interrupt::interrupted();
+    if failed.is_empty() && !interrupted {
+        return Ok(summary);
+    }
+    Err(BuildAllError {
+        failed,
+        skipped,
+        interrupted,
+        summary,
+    })
+}
+
+/// An external program unpak needs that isn't on `PATH`.
+#[derive(Debug)]
+pub struct MissingTool {
+    /// The name of the tool, or its path where one was configured.
+    tool: String,
+    /// The package that usually provides the tool.
+    package: &'static str,
+}
+
+impl fmt::Display for MissingTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let place = if self.tool.contains('/') {
+            ""
+        } else {
+            " on PATH"
+        };
+        write!(
+            f,
+            "{} not found{place}. Install the `{}` package.",
+            self.tool, self.package
+        )
+    }
+}
+
+impl std::error::Error for MissingTool {}
+
+/// A kernel setting that stops unprivileged users from creating user namespaces, which bwrap
+/// needs unless it is installed setuid root.
+#[derive(Debug)]
+pub struct UsernsRestriction {
+    /// The sysctl in the way, such as `kernel.unprivileged_userns_clone`.
+    pub sysctl: &'static str,
+    /// The value of it that lifts the restriction.
+    pub allow: &'static str,
+}
+
+impl fmt::Display for UsernsRestriction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unprivileged user namespaces are disabled by the {} sysctl; enable them with \
+             `sysctl {}={}`, or install bwrap setuid root",
+            self.sysctl, self.sysctl, self.allow
+        )
+    }
+}
+
+impl std::error::Error for UsernsRestriction {}
+
+/// The setting that keeps unprivileged user namespaces from this host, if one does: Debian and
+/// Ubuntu's `unprivileged_userns_clone`, a `max_user_namespaces` of 0, or the AppArmor
+/// restriction of newer Ubuntu releases.
+pub fn userns_restriction() -> Option<UsernsRestriction> {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|value| value.trim().to_owned())
+    };
+    let settings = [
+        ("kernel.unprivileged_userns_clone", "0", "1"),
+        ("user.max_user_namespaces", "0", "15000"),
+        ("kernel.apparmor_restrict_unprivileged_userns", "1", "0"),
+    ];
+    settings
+        .into_iter()
+        .find(|(sysctl, denied, _)| {
+            let path = format!("/proc/sys/{}", sysctl.replace('.', "/"));
+            read(&path).as_deref() == Some(*denied)
+        })
+        .map(|(sysctl, _, allow)| UsernsRestriction { sysctl, allow })
+}
+
+/// Whether the bwrap at `path`, looked up on `PATH`, runs as root and so isn't bound by what
+/// unprivileged users may do: unpak is running as root, or bwrap is setuid root.
+fn bwrap_is_privileged(path: &Path) -> bool {
+    // SAFETY: geteuid has no memory safety requirements
+    if unsafe { libc::geteuid() } == 0 {
+        return true;
+    }
+    which::which(path)
+        .ok()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .is_some_and(|metadata| metadata.uid() == 0 && metadata.mode() & libc::S_ISUID != 0)
+}
+
+/// Whether the bwrap at `path` can create a user namespace, trying it with a sandbox of the whole
+/// host.
+fn bwrap_unshares_user(path: &Path) -> bool {
+    Command::new(path)
+        .args(["--unshare-user", "--ro-bind", "/", "/", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The [`UsernsRestriction`] of this host as an error, if the bwrap at `path` exited with
+/// `status` because of it. bwrap exits with 1 when it can't set up a sandbox, which is what it
+/// does every time a restriction holds it back. The restricting sysctls don't bind a bwrap that
+/// is privileged or allowed by an AppArmor profile of its own, so they only count if bwrap can't
+/// create a user namespace even on its own.
+fn userns_failure(path: &Path, status: ExitStatus) -> Option<io::Error> {
+    if status.code() != Some(1) {
+        return None;
+    }
+    userns_restriction()
+        .filter(|_| !bwrap_is_privileged(path) && !bwrap_unshares_user(path))
+        .map(|restriction| io::Error::new(ErrorKind::PermissionDenied, restriction))
+}
+
+/// Checks up front that bwrap, and patchelf if `patchelf` is set, can be found.
+pub fn ensure_tooling(patchelf: bool) -> Result<(), MissingTool> {
+    let mut tools = vec![(bwrap_path(), "bubblewrap")];
+    if patchelf {
+        tools.push((PathBuf::from("patchelf"), "patchelf"));
+    }
+
+    for (tool, package) in tools {
+        if which::which(&tool).is_err() {
+            return Err(MissingTool {
+                tool: tool.display().to_string(),
+                package,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum PatchFailure {
+    MissingPatchelf,
+    Io(io::Error),
+    Exit(ExitStatus),
+}
+
+/// A program whose interpreter or library search path couldn't be patched.
+#[derive(Debug)]
+pub struct PatchError {
+    program: PathBuf,
+    failure: PatchFailure,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program.display();
+        match &self.failure {
+            PatchFailure::MissingPatchelf => {
+                write!(
+                    f,
+                    "cannot patch {program}: patchelf not found. Is patchelf installed?"
+                )
+            }
+            PatchFailure::Io(e) => write!(f, "cannot patch {program}: {e}"),
+            PatchFailure::Exit(status) => {
+                write!(f, "patchelf failed on {program}: {status}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.failure {
+            PatchFailure::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `program` needs its ELF interpreter patched: it is a dynamically linked ELF file
+/// that doesn't already use the sandbox's ld-linux.
+fn needs_patch(program: &Path) -> Result<bool, PatchError> {
+    let error = |e| PatchError {
+        program: program.to_path_buf(),
+        failure: PatchFailure::Io(e),
+    };
+
+    if !is_elf(program).map_err(error)? {
+        info!("skipping {}: not an ELF file", program.display());
+        return Ok(false);
+    }
+    match elf_interpreter(program).map_err(error)? {
+        None => {
+            info!("skipping {}: statically linked", program.display());
+            Ok(false)
+        }
+        Some(interpreter) if interpreter == Path::new(SBX_LD_LINUX) => {
+            info!(
+                "skipping {}: already uses {SBX_LD_LINUX}",
+                program.display()
+            );
+            Ok(false)
+        }
+        Some(_) => Ok(true),
+    }
+}
+
+/// Runs `patchelf` with `args` on `programs` at once, blaming the first of them for failures.
+fn run_patchelf(args: &[&str], programs: &[&Path]) -> Result<(), PatchError> {
+    let error = |failure| PatchError {
+        program: programs
+            .first()
+            .copied()
+            .unwrap_or(Path::new(""))
+            .to_path_buf(),
+        failure,
+    };
+
+    let status = Command::new("patchelf")
+        .args(args)
+        .args(programs)
+        .spawn()
+        .and_then(|mut proc| proc.wait())
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => error(PatchFailure::MissingPatchelf),
+            _ => error(PatchFailure::Io(e)),
+        })?;
+
+    if !status.success() {
+        return Err(error(PatchFailure::Exit(status)));
+    }
+    Ok(())
+}
+
+/// Points the ELF interpreter of `program` at the sandbox's ld-linux. Files that aren't ELF,
+/// such as scripts, and programs that don't need it are left alone.
+pub fn patch_noncompliant(program: &Path) -> Result<(), PatchError> {
+    if !needs_patch(program)? {
+        return Ok(());
+    }
+    run_patchelf(&["--set-interpreter", SBX_LD_LINUX], &[program])
+}
+
+/// Sets the library search path of `program` to `rpath`, such as `/usr/lib` so that it finds
+/// its shared libraries in the sandbox without `LD_LIBRARY_PATH`. patchelf writes a
+/// `DT_RUNPATH`, which unlike `DT_RPATH` still lets `LD_LIBRARY_PATH` take precedence.
+pub fn set_rpath(program: &Path, rpath: &str) -> Result<(), PatchError> {
+    run_patchelf(&["--set-rpath", rpath], &[program])
+}
+
+/// Removes the library search path of `program`, so that it only finds shared libraries in
+/// the standard locations.
+pub fn remove_rpath(program: &Path) -> Result<(), PatchError> {
+    run_patchelf(&["--remove-rpath"], &[program])
+}
+
+/// Patches every one of `programs` like [`patch_noncompliant`], with a single patchelf run for
+/// all that need it. The results are in the order of `programs`.
+///
+/// If that run fails, the programs are patched one at a time to tell which of them failed.
+pub fn patch_many(programs: &[PathBuf]) -> Vec<Result<(), PatchError>> {
+    let mut results: Vec<Result<(), PatchError>> = Vec::with_capacity(programs.len());
+    let mut pending = Vec::new();
+    for (i, program) in programs.iter().enumerate() {
+        match needs_patch(program) {
+            Ok(true) => pending.push(i),
+            Ok(false) => {}
+            Err(e) => {
+                results.push(Err(e));
+                continue;
+            }
+        }
+        results.push(Ok(()));
+    }
+    if pending.is_empty() {
+        return results;
+    }
+
+    let args = ["--set-interpreter", SBX_LD_LINUX];
+    let batch: Vec<&Path> = pending.iter().map(|&i| programs[i].as_path()).collect();
+    match run_patchelf(&args, &batch) {
+        Ok(()) => {}
+        Err(PatchError {
+            failure: PatchFailure::MissingPatchelf,
+            ..
+        }) => {
+            for &i in &pending {
+                results[i] = Err(PatchError {
+                    program: programs[i].clone(),
+                    failure: PatchFailure::MissingPatchelf,
+                });
+            }
+        }
+        Err(_) => {
+            for &i in &pending {
+                results[i] = run_patchelf(&args, &[&programs[i]]);
+            }
+        }
+    }
+    results
+}
+
+/// Runs `ldd` on `program` and returns the host paths of the shared libraries it links against.
+///
+/// Statically linked programs and files that aren't dynamic executables have no libraries.
+fn resolve_shared_libs(program: &Path) -> io::Result<Vec<HostPath>> {
+    let output = Command::new("ldd").arg(program).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if stdout.contains("statically linked")
+        || stdout.contains("not a dynamic executable")
+        || stderr.contains("not a dynamic executable")
+    {
+        return Ok(Vec::new());
+    }
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ldd {} failed: {}",
+            program.display(),
+            stderr.trim()
+        )));
+    }
+
+    // lines look like `libc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f...)`
+    let libs = stdout
+        .lines()
+        .filter_map(|line| line.split_once("=>"))
+        .filter_map(|(_, resolved)| resolved.split_whitespace().next())
+        .filter(|path| path.starts_with('/'))
+        .map(HostPath::from)
+        .collect();
+    Ok(libs)
+}
+
+/* unpak/bdeps */
+/* unpak/rdeps */
+
+#[derive(Clone)]
+pub enum StdMountLocation {
+    UserExe,
+    UserSo,
+}
+
+const FHS_EXE: &str = "/usr/bin";
+const FHS_SO: &str = "/usr/lib";
+
+impl StdMountLocation {
+    fn into_absolute_path(self) -> PathBuf {
+        match self {
+            StdMountLocation::UserExe => FHS_EXE.into(),
+            StdMountLocation::UserSo => FHS_SO.into(),
+        }
+    }
+
+    fn as_path(&self) -> &'static Path {
+        match self {
+            StdMountLocation::UserExe => Path::new(FHS_EXE),
+            StdMountLocation::UserSo => Path::new(FHS_SO),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HostPath(pub PathBuf);
+#[derive(Clone)]
+pub struct SbxPath(pub PathBuf);
+
+impl<T: Into<PathBuf>> From<T> for HostPath {
+    fn from(value: T) -> Self {
+        HostPath(value.into())
+    }
+}
+
+impl<T: Into<PathBuf>> From<T> for SbxPath {
+    fn from(value: T) -> Self {
+        SbxPath(value.into())
+    }
+}
+
+/// Creates a symlink at `dest` that points to `src`, like `ln -s src dest`.
+///
+/// This is passed to bwrap as `--symlink <src> <dest>`, target first, so this makes `/bin` a
+/// link to `/usr/bin`:
+///
+/// ```
+/// use unpak::{Bubblewrap, Symlink};
+///
+/// let sandbox = Bubblewrap::new().with_symlink(Symlink {
+///     src: "/usr/bin".into(),
+///     dest: "/bin".into(),
+/// });
+/// assert!(sandbox.shell_command().contains(" --symlink /usr/bin /bin"));
+/// ```
+#[derive(Clone)]
+pub struct Symlink {
+    /// Where to create the symlink. Always an absolute sandbox path.
+    pub dest: SbxPath,
+    /// What the symlink points to, the link target. Relative targets resolve against the
+    /// directory containing `dest`.
+    pub src: SbxPath,
+}
+
+#[derive(Clone)]
+pub enum Mount {
+    /// An empty directory, with octal permissions `perms` or bwrap's default of `0755`.
+    Touch {
+        sbx_path: SbxPath,
+        perms: Option<u32>,
+    },
+    /// An in-memory scratch filesystem, optionally limited to `size` bytes.
+    Tmpfs {
+        sbx_path: SbxPath,
+        size: Option<u64>,
+    },
+    /// A fresh procfs.
+    Proc { sbx_path: SbxPath },
+    /// A minimal devtmpfs with `null`, `zero`, `random`, the tty and friends.
+    Dev { sbx_path: SbxPath },
+    Fs {
+        readonly: bool,
+        /// Skip the mount instead of failing if `host_path` doesn't exist.
+        try_bind: bool,
+        host_path: HostPath,
+        sbx_path: SbxPath,
+    },
+    /// A bind that keeps device nodes under `host_path` usable, such as `/dev/dri` for GPU
+    /// access. This hands the sandbox real hardware, so only use it where a build needs it.
+    DevBind {
+        host_path: HostPath,
+        sbx_path: SbxPath,
+    },
+    /// A file created in the sandbox with `contents`, which don't have to exist on the host.
+    /// `perms` are the octal permissions, `0666` minus the umask if unset.
+    File {
+        contents: Vec<u8>,
+        sbx_path: SbxPath,
+        perms: Option<u32>,
+    },
+    /// A file created in the sandbox with whatever `reader` holds, read by bwrap until its end,
+    /// for secrets such as tokens that the host filesystem and command lines shouldn't carry.
+    ///
+    /// Unlike [`Mount::File`], the contents never touch the host's disk: bwrap reads `reader`
+    /// into a file of its own tmpfs, so only the sandbox sees them. The descriptor is passed as
+    /// an inherited descriptor, never by path, and unpak closes it once the sandbox has started;
+    /// only spawn a sandbox with it once, as a pipe is empty after the first read. Whatever
+    /// writes the other end of a pipe has to close it, or bwrap waits for more forever.
+    BindData {
+        reader: Arc<OwnedFd>,
+        sbx_path: SbxPath,
+    },
+    /// `lower` layered read-only under the writable `upper`, so writes never reach `lower`.
+    ///
+    /// `work` is scratch space for overlayfs and must be an empty directory on the same
+    /// filesystem as `upper`. Later `lower` layers are stacked on top of earlier ones.
+    Overlay {
+        lower: Vec<HostPath>,
+        upper: HostPath,
+        work: HostPath,
+        sbx_path: SbxPath,
+    },
+    /// Every host path matching the glob `host_pattern`, such as
+    /// `/usr/lib/x86_64-linux-gnu/libssl*`, bound by file name under `base_sbx`.
+    ///
+    /// Expanded into one `Mount::Fs` per match when the sandbox is assembled, so the bwrap
+    /// command line still lists every bind. Matching nothing is an error.
+    Glob {
+        host_pattern: String,
+        base_sbx: StdMountLocation,
+        readonly: bool,
+    },
+}
+
+impl<A: Into<HostPath>, B: Into<SbxPath>> From<(A, B)> for Mount {
+    fn from((host, sbx): (A, B)) -> Self {
+        Mount::Fs {
+            readonly: true,
+            try_bind: false,
+            host_path: host.into(),
+            sbx_path: sbx.into(),
+        }
+    }
+}
+
+/// Why mounts couldn't be placed in the sandbox.
+#[derive(Debug)]
+pub enum MountError {
+    /// A standard location mount of a host path without a file name, like `/` or `..`.
+    NoFileName(PathBuf),
+    /// Two different host files would be mounted at the same sandbox path.
+    Collision {
+        sbx_path: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    /// A `Mount::Glob` pattern that isn't a valid glob.
+    BadPattern {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    /// A `Mount::Glob` pattern that matched nothing, most likely a typo.
+    NoMatch(String),
+    /// A host path outside of every path mounts are restricted to.
+    NotAllowed(PathBuf),
+    /// Host paths to mount that don't exist, all of them at once.
+    Missing(Vec<PathBuf>),
+}
+
+impl fmt::Display for MountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountError::NoFileName(path) => write!(
+                f,
+                "cannot mount {} at a standard location: it has no file name",
+                path.display()
+            ),
+            MountError::Collision {
+                sbx_path,
+                first,
+                second,
+            } => write!(
+                f,
+                "both {} and {} would be mounted at {}",
+                first.display(),
+                second.display(),
+                sbx_path.display()
+            ),
+            MountError::BadPattern { pattern, source } => {
+                write!(f, "invalid glob {pattern}: {source}")
+            }
+            MountError::NoMatch(pattern) => write!(f, "glob {pattern} matches nothing"),
+            MountError::NotAllowed(path) => write!(
+                f,
+                "{} may not be mounted: it is not under any allowed path",
+                path.display()
+            ),
+            MountError::Missing(paths) => {
+                write!(f, "host paths to mount do not exist:")?;
+                for path in paths {
+                    write!(f, "\n    {}", path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MountError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MountError::BadPattern { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<MountError> for io::Error {
+    fn from(e: MountError) -> Self {
+        io::Error::new(ErrorKind::InvalidInput, e)
+    }
+}
+
+impl<T: Into<HostPath>> TryFrom<(T, StdMountLocation)> for Mount {
+    type Error = MountError;
+
+    fn try_from((host, base_sbx): (T, StdMountLocation)) -> Result<Self, Self::Error> {
+        let host_path = host.into();
+        let Some(filename) = host_path.0.file_name() else {
+            return Err(MountError::NoFileName(host_path.0));
+        };
+        let sbx_path = base_sbx.into_absolute_path().join(filename).into();
+
+        Ok(Mount::Fs {
+            host_path,
+            sbx_path,
+            readonly: true,
+            try_bind: false,
+        })
+    }
+}
+
+/// A host path that should be bound writable, as in `(Writable(path), StdMountLocation::UserSo).try_into()`.
+pub struct Writable(pub PathBuf);
+
+impl TryFrom<(Writable, StdMountLocation)> for Mount {
+    type Error = MountError;
+
+    fn try_from(
+        (Writable(host), base_sbx): (Writable, StdMountLocation),
+    ) -> Result<Self, Self::Error> {
+        let mut mount = Mount::try_from((host, base_sbx))?;
+        if let Mount::Fs { readonly, .. } = &mut mount {
+            *readonly = false;
+        }
+        Ok(mount)
+    }
+}
+
+impl Mount {
+    /// A read-only bind of `host` at `sbx`, the same as `(host, sbx).into()`.
+    ///
+    /// ```
+    /// use unpak::Mount;
+    ///
+    /// let mount = Mount::ro_bind("/usr/bin/make", "/usr/bin/make");
+    /// assert_eq!(mount.sbx_path(), std::path::Path::new("/usr/bin/make"));
+    /// ```
+    pub fn ro_bind(host: impl Into<HostPath>, sbx: impl Into<SbxPath>) -> Self {
+        (host, sbx).into()
+    }
+
+    /// A writable bind of `host` at `sbx`.
+    ///
+    /// ```
+    /// use unpak::Mount;
+    ///
+    /// let mount = Mount::rw_bind("/tmp/build-output", "/out");
+    /// assert_eq!(mount.sbx_path(), std::path::Path::new("/out"));
+    /// ```
+    pub fn rw_bind(host: impl Into<HostPath>, sbx: impl Into<SbxPath>) -> Self {
+        Mount::Fs {
+            readonly: false,
+            try_bind: false,
+            host_path: host.into(),
+            sbx_path: sbx.into(),
+        }
+    }
+
+    /// A read-only bind of `host` by its file name under the standard location `location`, the
+    /// same as `(host, location).try_into()`. Fails for paths without a file name, like `/`.
+    ///
+    /// ```
+    /// use unpak::{Mount, StdMountLocation};
+    ///
+    /// let mount = Mount::into_std("/opt/tools/bin/ninja", StdMountLocation::UserExe)?;
+    /// assert_eq!(mount.sbx_path(), std::path::Path::new("/usr/bin/ninja"));
+    /// # Ok::<(), unpak::MountError>(())
+    /// ```
+    pub fn into_std(
+        host: impl Into<HostPath>,
+        location: StdMountLocation,
+    ) -> Result<Self, MountError> {
+        (host, location).try_into()
+    }
+
+    /// A bind of `host` at the same path in the sandbox, such as `/nix/store` for programs that
+    /// refer to it by absolute path.
+    pub fn same_path(host: PathBuf, readonly: bool) -> Self {
+        Mount::Fs {
+            readonly,
+            try_bind: false,
+            host_path: host.clone().into(),
+            sbx_path: host.into(),
+        }
+    }
+
+    /// A read-only bind of every host path matching `host_pattern` under `base_sbx`.
+    pub fn glob(host_pattern: impl Into<String>, base_sbx: StdMountLocation) -> Self {
+        Mount::Glob {
+            host_pattern: host_pattern.into(),
+            base_sbx,
+            readonly: true,
+        }
+    }
+
+    /// Where the mount appears in the sandbox.
+    pub fn sbx_path(&self) -> &Path {
+        match self {
+            Mount::Touch { sbx_path, .. }
+            | Mount::Tmpfs { sbx_path, .. }
+            | Mount::Proc { sbx_path }
+            | Mount::Dev { sbx_path }
+            | Mount::Fs { sbx_path, .. }
+            | Mount::DevBind { sbx_path, .. }
+            | Mount::File { sbx_path, .. }
+            | Mount::BindData { sbx_path, .. }
+            | Mount::Overlay { sbx_path, .. } => &sbx_path.0,
+            Mount::Glob { base_sbx, .. } => base_sbx.as_path(),
+        }
+    }
+
+    /// Describes the mount for the event log.
+    fn event(&self) -> Event<'_> {
+        let (kind, host) = match self {
+            Mount::Touch { .. } => ("dir", None),
+            Mount::Tmpfs { .. } => ("tmpfs", None),
+            Mount::Proc { .. } => ("proc", None),
+            Mount::Dev { .. } => ("dev", None),
+            Mount::Fs {
+                readonly,
+                try_bind,
+                host_path,
+                ..
+            } => {
+                let kind = bind_flag(*readonly, *try_bind).trim_start_matches('-');
+                (kind, Some(host_path.0.as_path()))
+            }
+            Mount::DevBind { host_path, .. } => ("dev-bind", Some(host_path.0.as_path())),
+            Mount::File { .. } => ("file", None),
+            Mount::BindData { .. } => ("bind-data", None),
+            Mount::Overlay { upper, .. } => ("overlay", Some(upper.0.as_path())),
+            Mount::Glob { host_pattern, .. } => ("glob", Some(Path::new(host_pattern))),
+        };
+        Event::MountAdded {
+            kind,
+            host,
+            sbx: self.sbx_path(),
+        }
+    }
+
+    /// Makes a bind skip a missing host path rather than failing the whole sandbox.
+    pub fn optional(mut self) -> Self {
+        if let Mount::Fs { try_bind, .. } = &mut self {
+            *try_bind = true;
+        }
+        self
+    }
+}
+
+/// Where a bind in [`Bubblewrap::mount_report`] comes from.
+fn bind_source(host_path: &HostPath, try_bind: bool) -> String {
+    if try_bind {
+        format!("<- {} (if it exists)", host_path.0.display())
+    } else {
+        format!("<- {}", host_path.0.display())
+    }
+}
+
+/// The system's programs and libraries, which builds may always bind when mounts are restricted.
+pub const DEFAULT_ALLOWED_PATHS: &[&str] = &["/usr", "/lib", "/lib64", "/bin", "/sbin"];
+
+/// The paths mounts are restricted to with `--restrict-mounts`: [`DEFAULT_ALLOWED_PATHS`],
+/// unpak's cache and temporary directories, and `extra`.
+pub fn allowed_paths(extra: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut allowed: Vec<PathBuf> = DEFAULT_ALLOWED_PATHS.iter().map(PathBuf::from).collect();
+    allowed.extend([cache::cache_dir(), std::env::temp_dir()]);
+    allowed.extend(extra);
+    // canonical, so that symlinked prefixes like /lib on merged-usr systems still match
+    allowed
+        .into_iter()
+        .filter_map(|path| std::fs::canonicalize(path).ok())
+        .collect()
+}
+
+/// Checks that every host path in `mounts` is under one of `allowed`, once symlinks and `..` are
+/// resolved. Paths that don't exist are left to bwrap, which can't bind them either.
+fn check_allowed_paths(mounts: &[Mount], allowed: &[PathBuf]) -> Result<(), MountError> {
+    let host_paths = mounts.iter().flat_map(|mount| match mount {
+        Mount::Fs { host_path, .. } | Mount::DevBind { host_path, .. } => vec![host_path],
+        Mount::Overlay {
+            lower, upper, work, ..
+        } => lower.iter().chain([upper, work]).collect(),
+        _ => Vec::new(),
+    });
+    for host_path in host_paths {
+        let Ok(canonical) = std::fs::canonicalize(&host_path.0) else {
+            continue;
+        };
+        if !allowed.iter().any(|prefix| canonical.starts_with(prefix)) {
+            return Err(MountError::NotAllowed(host_path.0.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Fails with every host path of `mounts` that doesn't exist, so they can all be fixed before
+/// the next run rather than one bwrap error at a time. `try_bind` mounts may be missing.
+fn check_host_paths(mounts: &[Mount]) -> Result<(), MountError> {
+    let host_paths = mounts.iter().flat_map(|mount| match mount {
+        Mount::Fs {
+            try_bind: false,
+            host_path,
+            ..
+        }
+        | Mount::DevBind { host_path, .. } => vec![host_path],
+        Mount::Overlay {
+            lower, upper, work, ..
+        } => lower.iter().chain([upper, work]).collect(),
+        _ => Vec::new(),
+    });
+    let mut missing: Vec<PathBuf> = Vec::new();
+    for host_path in host_paths {
+        if !host_path.0.exists() && !missing.contains(&host_path.0) {
+            missing.push(host_path.0.clone());
+        }
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(MountError::Missing(missing))
+    }
+}
+
+/// Replaces each `Mount::Glob` in `mounts` with a bind of every path it matches, in sorted order.
+fn expand_globs(mounts: Vec<Mount>) -> Result<Vec<Mount>, MountError> {
+    let mut expanded = Vec::with_capacity(mounts.len());
+    for mount in mounts {
+        let Mount::Glob {
+            host_pattern,
+            base_sbx,
+            readonly,
+        } = mount
+        else {
+            expanded.push(mount);
+            continue;
+        };
+
+        let paths = glob::glob(&host_pattern).map_err(|source| MountError::BadPattern {
+            pattern: host_pattern.clone(),
+            source,
+        })?;
+        let before = expanded.len();
+        for path in paths {
+            match path {
+                Ok(path) if readonly => {
+                    expanded.push(Mount::try_from((path, base_sbx.clone()))?);
+                }
+                Ok(path) => {
+                    expanded.push(Mount::try_from((Writable(path), base_sbx.clone()))?);
+                }
+                Err(e) => warn!(
+                    "skipping {} while expanding {host_pattern}: {e}",
+                    e.path().display()
+                ),
+            }
+        }
+        if expanded.len() == before {
+            return Err(MountError::NoMatch(host_pattern));
+        }
+    }
+    Ok(expanded)
+}
+
+/// The bwrap flag for a bind mount.
+fn bind_flag(readonly: bool, try_bind: bool) -> &'static str {
+    match (readonly, try_bind) {
+        (true, false) => "--ro-bind",
+        (true, true) => "--ro-bind-try",
+        (false, false) => "--bind",
+        (false, true) => "--bind-try",
+    }
+}
+
+/// Collapses mounts that target the same sandbox path, keeping the first bind, among which
+/// `Mount::Overlay` counts.
+///
+/// A `Mount::Touch` is dropped if a `Mount::Fs`, `Mount::DevBind` or `Mount::Overlay` mounts the
+/// same path, regardless of order.
+fn dedup_mounts(mounts: &[Mount]) -> Vec<&Mount> {
+    let bound: HashSet<PathBuf> = mounts
+        .iter()
+        .filter_map(|mount| match mount {
+            Mount::Fs { sbx_path, .. }
+            | Mount::DevBind { sbx_path, .. }
+            | Mount::Overlay { sbx_path, .. } => Some(sbx_path.0.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut binds: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut touched = HashSet::new();
+    let mut special = HashSet::new();
+    mounts
+        .iter()
+        .filter(|mount| match mount {
+            Mount::Touch { sbx_path, .. } => {
+                !bound.contains(&sbx_path.0) && touched.insert(sbx_path.0.clone())
+            }
+            Mount::Tmpfs { sbx_path, .. }
+            | Mount::Proc { sbx_path }
+            | Mount::Dev { sbx_path }
+            | Mount::File { sbx_path, .. }
+            | Mount::BindData { sbx_path, .. } => special.insert(sbx_path.0.clone()),
+            Mount::Glob { .. } => true,
+            // an overlay binds its writable layer, as far as other binds are concerned
+            Mount::Fs {
+                host_path,
+                sbx_path,
+                ..
+            }
+            | Mount::DevBind {
+                host_path,
+                sbx_path,
+            }
+            | Mount::Overlay {
+                upper: host_path,
+                sbx_path,
+                ..
+            } => match binds.entry(sbx_path.0.clone()) {
+                Entry::Occupied(first) => {
+                    if *first.get() != host_path.0 {
+                        warn!(
+                            "ignoring mount of {} at {}, already bound to {}",
+                            host_path.0.display(),
+                            sbx_path.0.display(),
+                            first.get().display()
+                        );
+                    }
+                    false
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(host_path.0.clone());
+                    true
+                }
+            },
+        })
+        .collect()
+}
+
+/// The directories of `PATH` in every sandbox.
+const SBX_PATH: &[&str] = &[FHS_EXE, "/usr/sbin"];
+
+/// A `PATH` for a sandbox with `mounts`: `/usr/bin` and `/usr/sbin`, then every other `bin` or
+/// `sbin` directory an executable file is bound into, such as `/nix/store/<hash>-foo/bin` for a
+/// [`Mount::same_path`]. Shared libraries are often executable too, hence the name check.
+pub fn exe_path(mounts: &[Mount]) -> OsString {
+    let mut dirs: Vec<&Path> = SBX_PATH.iter().map(Path::new).collect();
+    for mount in mounts {
+        let Mount::Fs {
+            host_path,
+            sbx_path,
+            ..
+        } = mount
+        else {
+            continue;
+        };
+        let executable = std::fs::metadata(&host_path.0)
+            .map(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0)
+            .unwrap_or(false);
+        let Some(dir) = sbx_path.0.parent() else {
+            continue;
+        };
+        let is_bin = matches!(
+            dir.file_name().and_then(OsStr::to_str),
+            Some("bin" | "sbin")
+        );
+        if executable && is_bin {
+            // a ':' would split the directory in two
+            let separable = !dir.as_os_str().as_encoded_bytes().contains(&b':');
+            if separable && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    std::env::join_paths(dirs).expect("no directory contains ':'")
+}
+
+pub struct Bubblewrap {
+    mounts: Vec<Mount>,
+    symlinks: Vec<Symlink>,
+    /// `PATH` in the sandbox, if unset [`exe_path`] of the mounts, or the inherited one when the
+    /// environment is.
+    path: Option<OsString>,
+    /// Working directory inside the sandbox.
+    chdir: Option<PathBuf>,
+    unshare_pid: bool,
+    unshare_net: bool,
+    /// Mount what networking programs need from the host's `/etc` when the network is shared.
+    network_files: bool,
+
+    new_session: bool,
+    detach_output: bool,
+    /// Hand the program's stdout and stderr to the caller through pipes.
+    pipe_output: bool,
+    die_with_parent: bool,
+    /// Capabilities to drop, by name like `CAP_NET_RAW`, or `ALL`.
+    cap_drop: Vec<String>,
+    hostname: Option<String>,
+    /// Identity of the sandboxed program, mapped from the invoking user in a new user namespace.
+    uid: Option<u32>,
+    gid: Option<u32>,
+    /// A compiled BPF program restricting syscalls, in the format described in [`seccomp`].
+    seccomp: Option<PathBuf>,
+    /// The SELinux or AppArmor label to run the program with.
+    exec_label: Option<String>,
+    /// The label of the files and directories bwrap creates in the sandbox.
+    file_label: Option<String>,
+
+    /// The bwrap to run, [`bwrap_path`] if unset.
+    bwrap_path: Option<PathBuf>,
+    program: Option<PathBuf>,
+    args: Vec<OsString>,
+    envvars: EnvVars,
+}
+
+/// The bwrap set by [`set_bwrap_path`].
+static BWRAP_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes sandboxes run the bwrap at `path` for the rest of the process, unless they name their
+/// own. Only the first call counts.
+pub fn set_bwrap_path(path: PathBuf) {
+    if BWRAP_PATH.set(path).is_err() {
+        warn!("bwrap path already set, ignoring");
+    }
+}
+
+/// The bwrap sandboxes run: the one from [`set_bwrap_path`], or else `$UNPAK_BWRAP`, or else
+/// `bwrap` from `PATH`.
+pub fn bwrap_path() -> PathBuf {
+    if let Some(path) = BWRAP_PATH.get() {
+        return path.clone();
+    }
+    std::env::var_os("UNPAK_BWRAP")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("bwrap"))
+}
+
+pub enum EnvVars {
+    Inherit,
+    Set(Vec<(OsString, OsString)>),
+}
+
+impl EnvVars {
+    /// The variables to set, switching from inheriting the environment to a cleared one first.
+    fn set_mut(&mut self) -> &mut Vec<(OsString, OsString)> {
+        if let EnvVars::Inherit = self {
+            warn!("setting an environment variable clears the inherited environment");
+            *self = EnvVars::Set(Vec::new());
+        }
+        match self {
+            EnvVars::Inherit => unreachable!("replaced above"),
+            EnvVars::Set(list) => list,
+        }
+    }
+}
+
+impl Default for Bubblewrap {
+    fn default() -> Self {
+        Bubblewrap::new()
+    }
+}
+
+impl Bubblewrap {
+    pub fn new() -> Self {
+        Self {
+            mounts: Vec::new(),
+            symlinks: Vec::new(),
+            path: None,
+            chdir: None,
+            unshare_pid: false,
+            unshare_net: false,
+            network_files: true,
+            new_session: false,
+            detach_output: false,
+            pipe_output: false,
+            die_with_parent: false,
+            cap_drop: Vec::new(),
+            hostname: None,
+            uid: None,
+            gid: None,
+            seccomp: None,
+            exec_label: None,
+            file_label: None,
+            bwrap_path: None,
+            program: None,
+            args: Vec::new(),
+            envvars: EnvVars::Inherit,
+        }
+    }
+
+    pub fn add_mount(&mut self, mount: Mount) -> &mut Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    pub fn with_mount(mut self, mount: Mount) -> Self {
+        self.add_mount(mount);
+        self
+    }
+
+    pub fn add_mounts(&mut self, mounts: impl IntoIterator<Item = Mount>) {
+        self.mounts.extend(mounts);
+    }
+
+    pub fn with_mounts(mut self, mounts: impl IntoIterator<Item = Mount>) -> Self {
+        self.add_mounts(mounts);
+        self
+    }
+
+    pub fn add_dir(&mut self, sbx_path: impl Into<SbxPath>, perms: Option<u32>) -> &mut Self {
+        self.add_mount(Mount::Touch {
+            sbx_path: sbx_path.into(),
+            perms,
+        })
+    }
+
+    pub fn add_tmpfs(&mut self, sbx_path: impl Into<SbxPath>, size: Option<u64>) -> &mut Self {
+        self.add_mount(Mount::Tmpfs {
+            sbx_path: sbx_path.into(),
+            size,
+        })
+    }
+
+    /// Binds `host` at the same path in the sandbox, see [`Mount::same_path`].
+    pub fn add_same_path(&mut self, host: impl Into<PathBuf>, readonly: bool) -> &mut Self {
+        self.add_mount(Mount::same_path(host.into(), readonly))
+    }
+
+    pub fn with_same_path(mut self, host: impl Into<PathBuf>, readonly: bool) -> Self {
+        self.add_same_path(host, readonly);
+        self
+    }
+
+    pub fn add_dev_bind(
+        &mut self,
+        host_path: impl Into<HostPath>,
+        sbx_path: impl Into<SbxPath>,
+    ) -> &mut Self {
+        self.add_mount(Mount::DevBind {
+            host_path: host_path.into(),
+            sbx_path: sbx_path.into(),
+        })
+    }
+
+    pub fn add_file(
+        &mut self,
+        contents: impl Into<Vec<u8>>,
+        sbx_path: impl Into<SbxPath>,
+        perms: Option<u32>,
+    ) -> &mut Self {
+        self.add_mount(Mount::File {
+            contents: contents.into(),
+            sbx_path: sbx_path.into(),
+            perms,
+        })
+    }
+
+    /// Adds a [`Mount::BindData`] of what `reader` holds, such as the read end of a pipe.
+    pub fn add_bind_data(
+        &mut self,
+        reader: impl Into<OwnedFd>,
+        sbx_path: impl Into<SbxPath>,
+    ) -> &mut Self {
+        self.add_mount(Mount::BindData {
+            reader: Arc::new(reader.into()),
+            sbx_path: sbx_path.into(),
+        })
+    }
+
+    pub fn add_overlay(
+        &mut self,
+        lower: impl IntoIterator<Item = impl Into<HostPath>>,
+        upper: impl Into<HostPath>,
+        work: impl Into<HostPath>,
+        sbx_path: impl Into<SbxPath>,
+    ) -> &mut Self {
+        self.add_mount(Mount::Overlay {
+            lower: lower.into_iter().map(Into::into).collect(),
+            upper: upper.into(),
+            work: work.into(),
+            sbx_path: sbx_path.into(),
+        })
+    }
+
+    pub fn add_symlink(&mut self, symlink: Symlink) -> &mut Self {
+        self.symlinks.push(symlink);
+        self
+    }
+
+    pub fn with_symlink(mut self, symlink: Symlink) -> Self {
+        self.add_symlink(symlink);
+        self
+    }
+
+    pub fn add_symlinks(&mut self, symlinks: impl IntoIterator<Item = Symlink>) -> &mut Self {
+        self.symlinks.extend(symlinks);
+        self
+    }
+
+    pub fn with_symlinks(mut self, symlinks: impl IntoIterator<Item = Symlink>) -> Self {
+        self.add_symlinks(symlinks);
+        self
+    }
+
+    pub fn set_program(&mut self, program: PathBuf) -> &mut Self {
+        self.program = Some(program);
+        self
+    }
+
+    pub fn with_program(mut self, program: PathBuf) -> Self {
+        self.set_program(program);
+        self
+    }
+
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<OsString>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn set_path(&mut self, path: impl Into<OsString>) -> &mut Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<OsString>) -> Self {
+        self.set_path(path);
+        self
+    }
+
+    /// Sets the working directory of the sandboxed program. `chdir` is a path in the sandbox
+    /// filesystem, not on the host.
+    pub fn set_chdir(&mut self, chdir: PathBuf) -> &mut Self {
+        self.chdir = Some(chdir);
+        self
+    }
+
+    pub fn with_chdir(mut self, chdir: PathBuf) -> Self {
+        self.set_chdir(chdir);
+        self
+    }
+
+    pub fn set_unshare_pid(&mut self, unshare_pid: bool) -> &mut Self {
+        self.unshare_pid = unshare_pid;
+        self
+    }
+
+    pub fn with_unshare_pid(mut self, unshare_pid: bool) -> Self {
+        self.set_unshare_pid(unshare_pid);
+        self
+    }
+
+    pub fn set_unshare_net(&mut self, unshare_net: bool) -> &mut Self {
+        self.unshare_net = unshare_net;
+        self
+    }
+
+    pub fn with_unshare_net(mut self, unshare_net: bool) -> Self {
+        self.set_unshare_net(unshare_net);
+        self
+    }
+
+    /// Whether a sandbox that shares the host's network also gets the host's CA certificates and
+    /// `/etc/resolv.conf`, read-only, so that name resolution and TLS work. On by default; has no
+    /// effect with the network unshared.
+    pub fn with_network_files(mut self, network_files: bool) -> Self {
+        self.network_files = network_files;
+        self
+    }
+
+    pub fn with_detach_stdout(mut self, detach_stdout: bool) -> Self {
+        self.detach_output = detach_stdout;
+        self
+    }
+
+    /// Spawns the sandbox with its stdout and stderr piped, for the caller to read from the
+    /// returned [`Child`]. Detached output takes precedence.
+    pub fn with_pipe_output(mut self, pipe_output: bool) -> Self {
+        self.pipe_output = pipe_output;
+        self
+    }
+
+    pub fn with_new_session(mut self, setsid: bool) -> Self {
+        self.new_session = setsid;
+        self
+    }
+
+    /// Kills the sandbox when unpak exits, so crashed builds don't leave orphaned processes.
+    pub fn with_die_with_parent(mut self, die_with_parent: bool) -> Self {
+        self.die_with_parent = die_with_parent;
+        self
+    }
+
+    pub fn add_cap_drop(&mut self, name: impl Into<String>) -> &mut Self {
+        self.cap_drop.push(name.into());
+        self
+    }
+
+    pub fn with_cap_drop_all(mut self) -> Self {
+        self.add_cap_drop("ALL");
+        self
+    }
+
+    /// Gives the sandbox its own UTS namespace with `hostname`, so the host's name can't leak
+    /// into build outputs.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Runs the program as `uid`, so builds don't see or record the invoking user's.
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Restricts the syscalls the sandboxed program may make with the BPF program in the file
+    /// at `filter`.
+    pub fn with_seccomp(mut self, filter: PathBuf) -> Self {
+        self.seccomp = Some(filter);
+        self
+    }
+
+    /// Runs the program with the mandatory access control label `label`, for hosts where the
+    /// default label isn't allowed to execute anything in the sandbox.
+    pub fn with_exec_label(mut self, label: impl Into<String>) -> Self {
+        self.exec_label = Some(label.into());
+        self
+    }
+
+    /// Labels the files and directories bwrap creates, such as `Mount::Touch` and `Mount::File`.
+    pub fn with_file_label(mut self, label: impl Into<String>) -> Self {
+        self.file_label = Some(label.into());
+        self
+    }
+
+    /// Runs the bwrap at `path` rather than [`bwrap_path`], such as a statically linked one or
+    /// a particular version.
+    pub fn with_bwrap_path(mut self, path: PathBuf) -> Self {
+        self.bwrap_path = Some(path);
+        self
+    }
+
+    fn bwrap(&self) -> PathBuf {
+        self.bwrap_path.clone().unwrap_or_else(bwrap_path)
+    }
+
+    pub fn with_inherit_env(mut self, inherit: bool) -> Self {
+        self.envvars = if inherit {
+            EnvVars::Inherit
+        } else {
+            EnvVars::Set(Vec::new())
+        };
+        self
+    }
+
+    /// Sets `id` to `value` in the sandbox.
+    ///
+    /// The sandbox only ever gets the variables it is given, so a sandbox still inheriting
+    /// unpak's environment stops doing so, with a warning, as if `with_inherit_env(false)` had
+    /// been called first.
+    pub fn add_envvar(&mut self, id: OsString, value: OsString) -> &mut Self {
+        self.envvars.set_mut().push((id, value));
+        self
+    }
+
+    pub fn with_envvar(mut self, id: OsString, value: OsString) -> Self {
+        self.add_envvar(id, value);
+        self
+    }
+
+    /// Copies the named variables from unpak's own environment into the sandbox, for a curated
+    /// set like `HOME` or `SSL_CERT_FILE` in an otherwise cleared environment.
+    ///
+    /// Fails if one of them isn't set, rather than quietly building without it.
+    pub fn with_pass_env(
+        mut self,
+        names: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> io::Result<Self> {
+        for name in names {
+            let name = name.into();
+            let Some(value) = std::env::var_os(&name) else {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("environment variable {} is not set", name.to_string_lossy()),
+                ));
+            };
+            self.add_envvar(name, value);
+        }
+        Ok(self)
+    }
+
+    /// The mounts in the order bwrap sets them up, which is the order of their arguments.
+    ///
+    /// bwrap creates each mount point as it goes, so a mount must come after whatever contains
+    /// it: `/usr/lib` before `/usr/lib/foo/bar.so`, or the tmpfs at `/tmp` before a file bound
+    /// into it. Mounts are therefore ordered by how deep their sandbox path is. Mounts at the same
+    /// depth keep the order they were added in.
+    ///
+    /// Globs are expanded here too; one that matches nothing is skipped with a warning, though
+    /// `spawn` refuses to start with one.
+    fn ordered_mounts(&self) -> Vec<Mount> {
+        let mut all = Vec::with_capacity(self.mounts.len());
+        for mount in &self.mounts {
+            match expand_globs(vec![mount.clone()]) {
+                Ok(expanded) => all.extend(expanded),
+                Err(e) => warn!("{e}"),
+            }
+        }
+        if self.network_files && !self.unshare_net {
+            all.extend(network_file_mounts());
+        }
+        let mut mounts: Vec<Mount> = dedup_mounts(&all).into_iter().cloned().collect();
+        mounts.sort_by_key(|mount| mount.sbx_path().components().count());
+        mounts
+    }
+
+    /// The arguments `spawn` passes to bwrap, including the program to run and its arguments.
+    fn command_line(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        let mut file_fd = FILE_FD_BASE;
+        // before the mounts, as it applies to everything created after it
+        if let Some(label) = &self.file_label {
+            args.extend(["--file-label".into(), label.into()]);
+        }
+        let mounts = self.ordered_mounts();
+        for mount in &mounts {
+            match mount {
+                Mount::Touch { sbx_path, perms } => {
+                    // --perms applies to the following --dir only
+                    if let Some(perms) = perms {
+                        args.extend(["--perms".into(), format!("{perms:04o}").into()]);
+                    }
+                    args.extend(["--dir".into(), sbx_path.0.clone().into()]);
+                }
+                Mount::Tmpfs { sbx_path, size } => {
+                    // --size applies to the following --tmpfs
+                    if let Some(size) = size {
+                        args.extend(["--size".into(), size.to_string().into()]);
+                    }
+                    args.extend(["--tmpfs".into(), sbx_path.0.clone().into()]);
+                }
+                Mount::Proc { sbx_path } => {
+                    args.extend(["--proc".into(), sbx_path.0.clone().into()]);
+                }
+                Mount::Dev { sbx_path } => {
+                    args.extend(["--dev".into(), sbx_path.0.clone().into()]);
+                }
+                Mount::Fs {
+                    readonly,
+                    try_bind,
+                    host_path,
+                    sbx_path,
+                } => {
+                    args.extend([
+                        bind_flag(*readonly, *try_bind).into(),
+                        host_path.0.clone().into(),
+                        sbx_path.0.clone().into(),
+                    ]);
+                }
+                Mount::DevBind {
+                    host_path,
+                    sbx_path,
+                } => {
+                    args.extend([
+                        "--dev-bind".into(),
+                        host_path.0.clone().into(),
+                        sbx_path.0.clone().into(),
+                    ]);
+                }
+                Mount::File {
+                    sbx_path, perms, ..
+                } => {
+                    // --perms applies to the following --file; spawn passes the contents on
+                    // descriptors from FILE_FD_BASE up, in mount order
+                    if let Some(perms) = perms {
+                        args.extend(["--perms".into(), format!("{perms:04o}").into()]);
+                    }
+                    args.extend([
+                        "--file".into(),
+                        file_fd.to_string().into(),
+                        sbx_path.0.clone().into(),
+                    ]);
+                    file_fd += 1;
+                }
+                Mount::BindData { sbx_path, .. } => {
+                    args.extend([
+                        "--bind-data".into(),
+                        file_fd.to_string().into(),
+                        sbx_path.0.clone().into(),
+                    ]);
+                    file_fd += 1;
+                }
+                Mount::Overlay {
+                    lower,
+                    upper,
+                    work,
+                    sbx_path,
+                } => {
+                    // each --overlay-src adds a lower layer to the following --overlay
+                    for layer in lower {
+                        args.extend(["--overlay-src".into(), layer.0.clone().into()]);
+                    }
+                    args.extend([
+                        "--overlay".into(),
+                        upper.0.clone().into(),
+                        work.0.clone().into(),
+                        sbx_path.0.clone().into(),
+                    ]);
+                }
+                Mount::Glob { .. } => unreachable!("ordered_mounts expands globs"),
+            }
+        }
+
+        for symlink in &self.symlinks {
+            // the target comes first, as with `ln -s`
+            args.extend([
+                "--symlink".into(),
+                symlink.src.0.clone().into(),
+                symlink.dest.0.clone().into(),
+            ]);
+        }
+
+        if let Some(chdir) = &self.chdir {
+            args.extend(["--chdir".into(), chdir.clone().into()]);
+        }
+
+        if self.unshare_pid {
+            args.push("--unshare-pid".into());
+        }
+
+        if self.unshare_net {
+            args.push("--unshare-net".into());
+        }
+
+        if let Some(hostname) = &self.hostname {
+            // --hostname requires its own UTS namespace
+            args.extend(["--unshare-uts".into(), "--hostname".into(), hostname.into()]);
+        }
+
+        if self.uid.is_some() || self.gid.is_some() {
+            // --uid and --gid only work in a user namespace of the sandbox's own
+            args.push("--unshare-user".into());
+            if let Some(uid) = self.uid {
+                args.extend(["--uid".into(), uid.to_string().into()]);
+            }
+            if let Some(gid) = self.gid {
+                args.extend(["--gid".into(), gid.to_string().into()]);
+            }
+        }
+
+        if self.new_session {
+            args.push("--new-session".into());
+        }
+
+        // bwrap applies these in order, so PATH comes after --clearenv and before the variables
+        // set explicitly, which may override it
+        if let EnvVars::Set(_) = &self.envvars {
+            args.push("--clearenv".into());
+        }
+        // an inherited PATH is left alone, unless one was set explicitly
+        let path = match (&self.path, &self.envvars) {
+            (Some(path), _) => Some(path.clone()),
+            (None, EnvVars::Set(_)) => Some(exe_path(&mounts)),
+            (None, EnvVars::Inherit) => None,
+        };
+        if let Some(path) = path {
+            args.extend(["--setenv".into(), "PATH".into(), path]);
+        }
+        if let EnvVars::Set(list) = &self.envvars {
+            for (id, value) in list {
+                args.extend(["--setenv".into(), id.clone(), value.clone()]);
+            }
+        }
+
+        if self.die_with_parent {
+            args.push("--die-with-parent".into());
+        }
+
+        for cap in &self.cap_drop {
+            args.extend(["--cap-drop".into(), cap.into()]);
+        }
+
+        if let Some(label) = &self.exec_label {
+            args.extend(["--exec-label".into(), label.into()]);
+        }
+
+        if self.seccomp.is_some() {
+            // spawn passes the filter on this descriptor
+            args.extend(["--seccomp".into(), SECCOMP_FD.to_string().into()]);
+        }
+
+        if let Some(program) = &self.program {
+            args.push(program.clone().into());
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+
+    /// The bwrap invocation as a line that can be pasted into a shell.
+    pub fn shell_command(&self) -> String {
+        let mut line = shell_quote(self.bwrap().as_os_str());
+        for arg in self.command_line() {
+            line.push(' ');
+            line.push_str(&shell_quote(&arg));
+        }
+        line
+    }
+
+    /// The sandbox filesystem as it would be assembled, globs and shared libraries included: what
+    /// is bound read-only, what is writable, what bwrap creates, and the symlinks, each sorted by
+    /// sandbox path.
+    pub fn mount_report(&self) -> String {
+        let mut readonly = Vec::new();
+        let mut writable = Vec::new();
+        let mut created = Vec::new();
+        for mount in self.ordered_mounts() {
+            let sbx = mount.sbx_path().to_path_buf();
+            let (section, from) = match &mount {
+                Mount::Fs {
+                    readonly: true,
+                    try_bind,
+                    host_path,
+                    ..
+                } => (&mut readonly, bind_source(host_path, *try_bind)),
+                Mount::Fs {
+                    try_bind,
+                    host_path,
+                    ..
+                } => (&mut writable, bind_source(host_path, *try_bind)),
+                Mount::DevBind { host_path, .. } => (
+                    &mut writable,
+                    format!("{} (with devices)", host_path.0.display()),
+                ),
+                Mount::Overlay { lower, upper, .. } => {
+                    let lower: Vec<String> = lower
+                        .iter()
+                        .map(|layer| layer.0.display().to_string())
+                        .collect();
+                    (
+                        &mut writable,
+                        format!(
+                            "overlay of {} under {}",
+                            lower.join(", "),
+                            upper.0.display()
+                        ),
+                    )
+                }
+                Mount::Tmpfs { .. } => (&mut writable, "tmpfs".to_owned()),
+                Mount::Touch { .. } => (&mut created, "directory".to_owned()),
+                Mount::File { contents, .. } => {
+                    (&mut created, format!("file of {} bytes", contents.len()))
+                }
+                Mount::BindData { .. } => (&mut created, "file read from a descriptor".to_owned()),
+                Mount::Proc { .. } => (&mut created, "procfs".to_owned()),
+                Mount::Dev { .. } => (&mut created, "devtmpfs".to_owned()),
+                Mount::Glob { .. } => unreachable!("globs are expanded"),
+            };
+            section.push((sbx, from));
+        }
+        let mut symlinks: Vec<(PathBuf, String)> = self
+            .symlinks
+            .iter()
+            .map(|symlink| {
+                (
+                    symlink.dest.0.clone(),
+                    format!("-> {}", symlink.src.0.display()),
+                )
+            })
+            .collect();
+
+        let mut report = String::new();
+        for (title, entries) in [
+            ("read-only", &mut readonly),
+            ("writable", &mut writable),
+            ("created", &mut created),
+            ("symlinks", &mut symlinks),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+            entries.sort();
+            report.push_str(title);
+            report.push_str(":\n");
+            for (sbx, from) in entries.iter() {
+                report.push_str(&format!("    {}  {from}\n", sbx.display()));
+            }
+        }
+        report
+    }
+
+    /// Warns about symlinks pointing at sandbox paths nothing is mounted at, which would dangle,
+    /// and about symlinks created at relative paths, which usually means `src` and `dest` were
+    /// swapped.
+    ///
+    /// Only a warning, since a link may deliberately point at something the sandboxed program
+    /// creates itself.
+    pub fn validate(&self) {
+        let present: Vec<&Path> = self
+            .mounts
+            .iter()
+            .map(Mount::sbx_path)
+            .chain(self.symlinks.iter().map(|symlink| symlink.dest.0.as_path()))
+            .collect();
+
+        for symlink in &self.symlinks {
+            if symlink.dest.0.is_relative() {
+                warn!(
+                    "symlink {} -> {} is created at a relative path; are src and dest swapped?",
+                    symlink.dest.0.display(),
+                    symlink.src.0.display()
+                );
+                continue;
+            }
+            // relative targets resolve against the directory the link is in
+            let target = match symlink.dest.0.parent() {
+                Some(dir) => dir.join(&symlink.src.0),
+                None => symlink.src.0.clone(),
+            };
+            // a mount makes its parent directories exist too, and a bound directory its contents
+            let resolves = present
+                .iter()
+                .any(|path| path.starts_with(&target) || target.starts_with(path));
+            if !resolves {
+                warn!(
+                    "symlink {} points at {}, which nothing is mounted at",
+                    symlink.dest.0.display(),
+                    symlink.src.0.display()
+                );
+            }
+        }
+    }
+
+    /// The warnings about terminals `spawn` gives, when unpak's output goes to a terminal with
+    /// `output_is_terminal` and its input comes from one with `stdin_is_terminal`.
+    fn terminal_warnings(
+        &self,
+        output_is_terminal: bool,
+        stdin_is_terminal: bool,
+    ) -> Vec<&'static str> {
+        // job control only matters to interactive sandboxes, and terminal injection to those
+        // that have a terminal at all, through their stdin or their output
+        let interactive = !self.detach_output && !self.pipe_output && output_is_terminal;
+        let mut warnings = Vec::new();
+        if self.new_session && interactive {
+            warnings.push("setsid will break job control.");
+        }
+        if !self.new_session && (interactive || stdin_is_terminal) {
+            warnings.push("sandbox escape may be possible because process can control terminal.");
+        }
+        warnings
+    }
+
+    pub fn spawn(mut self) -> io::Result<Child> {
+        if self.program.is_none() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "a program to run in the sandbox is required",
+            ));
+        }
+
+        if !self.unshare_net && matches!(self.envvars, EnvVars::Inherit) {
+            warn!("network is shared and environment variables are inherited; the sandbox is not hermetic.");
+        }
+
+        if let EnvVars::Inherit = self.envvars {
+            warn!("environment variables are inherited");
+        }
+
+        let output_is_terminal = io::stdout().is_terminal() || io::stderr().is_terminal();
+        for warning in self.terminal_warnings(output_is_terminal, io::stdin().is_terminal()) {
+            warn!("{warning}");
+        }
+
+        self.mounts = expand_globs(self.mounts)?;
+        check_host_paths(&self.mounts)?;
+        for mount in &self.mounts {
+            match mount {
+                Mount::Overlay { upper, work, .. } => check_same_fs(&upper.0, &work.0)?,
+                Mount::DevBind { host_path, .. } => warn!(
+                    "{} is bound with device access; the sandbox can reach host hardware.",
+                    host_path.0.display()
+                ),
+                _ => {}
+            }
+        }
+
+        self.validate();
+        debug!("running {}", self.shell_command());
+        for mount in &self.ordered_mounts() {
+            events::record(&mount.event());
+        }
+
+        let mut cmd = Command::new(self.bwrap());
+        cmd.args(self.command_line());
+
+        // these only have to stay open until bwrap has started and inherited them
+        let mut inherited: Vec<(OwnedFd, i32)> = Vec::new();
+        if let Some(filter) = &self.seccomp {
+            inherited.push((File::open(filter)?.into(), SECCOMP_FD));
+        }
+        let mut file_fd = FILE_FD_BASE;
+        for mount in self.ordered_mounts() {
+            let file = match mount {
+                Mount::File { contents, .. } => unlinked_file(&contents)?.into(),
+                Mount::BindData { reader, .. } => reader.try_clone()?,
+                _ => continue,
+            };
+            inherited.push((file, file_fd));
+            file_fd += 1;
+        }
+        let mappings = inherited
+            .iter()
+            .map(|(file, child_fd)| FdMapping {
+                parent_fd: file.as_raw_fd(),
+                child_fd: *child_fd,
+            })
+            .collect();
+        cmd.fd_mappings(mappings)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+        if self.detach_output {
+            cmd.stdout(Stdio::null());
+            cmd.stderr(Stdio::null());
+        } else if self.pipe_output {
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        }
+
+        let child = cmd.spawn();
+        // the sandbox has its own copies of the descriptors of data binds now
+        self.mounts
+            .retain(|mount| !matches!(mount, Mount::BindData { .. }));
+        child
+    }
+}
+
+/// A file holding `contents`, opened for reading from the start, that is already gone from the
+/// host filesystem.
+fn unlinked_file(contents: &[u8]) -> io::Result<File> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("contents");
+    std::fs::write(&path, contents)?;
+    // dropping `dir` removes the file, but it lives on as long as it is open
+    File::open(path)
+}
+
+/// Host files that let programs reach the network: the resolver configuration and the CA
+/// certificates, which live in different places across distributions.
+const NETWORK_FILES: &[&str] = &[
+    "/etc/resolv.conf",
+    // Debian, Ubuntu, Arch, openSUSE; a symlink into /etc/pki on Fedora
+    "/etc/ssl/certs",
+    // what the certificates in Debian's /etc/ssl/certs link to
+    "/usr/share/ca-certificates",
+    // Fedora, RHEL
+    "/etc/pki/tls/certs",
+    "/etc/pki/ca-trust/extracted",
+    // Alpine, and the bundle OpenSSL looks for by default on some others
+    "/etc/ssl/cert.pem",
+];
+
+/// Read-only binds of the [`NETWORK_FILES`] this host has, at the same paths.
+fn network_file_mounts() -> Vec<Mount> {
+    NETWORK_FILES
+        .iter()
+        .filter(|path| Path::new(path).exists())
+        .map(|&path| Mount::from((path, path)))
+        .collect()
+}
+
+/// overlayfs needs an overlay's upper and work directories on the same filesystem, and bwrap only
+/// reports a bare `EXDEV` once the sandbox is half set up.
+fn check_same_fs(upper: &Path, work: &Path) -> io::Result<()> {
+    let upper_dev = std::fs::metadata(upper)?.dev();
+    let work_dev = std::fs::metadata(work)?.dev();
+    if upper_dev != work_dev {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "overlay upper dir {} and work dir {} are on different filesystems",
+                upper.display(),
+                work.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Quotes `arg` for a POSIX shell, so logged command lines can be pasted into a terminal.
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.into_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Discovers the shared libraries needed by every executable in `mounts` and
+/// returns mounts for the ones that aren't already present.
+///
+/// Libraries are mounted by file name, so two different libraries sharing one, or a library
+/// shadowing an existing mount, is an error naming both host paths. The same file reached
+/// through different paths, such as `/lib` and `/usr/lib` on merged-usr systems, is not.
+fn shared_lib_mounts(mounts: &[Mount]) -> Result<Vec<Mount>, MountError> {
+    let mut seen: HashSet<PathBuf> = mounts
+        .iter()
+        .filter_map(|mount| match mount {
+            Mount::Fs { host_path, .. } => Some(host_path.0.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut placed: HashMap<PathBuf, PathBuf> = mounts
+        .iter()
+        .filter_map(|mount| match mount {
+            Mount::Fs {
+                host_path,
+                sbx_path,
+                ..
+            } => Some((sbx_path.0.clone(), host_path.0.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut libs = Vec::new();
+    for mount in mounts {
+        let Mount::Fs {
+            host_path,
+            sbx_path,
+            ..
+        } = mount
+        else {
+            continue;
+        };
+        if !sbx_path.0.starts_with(FHS_EXE) {
+            continue;
+        }
+
+        let resolved = cache::cached_shared_libs(&host_path.0, |program| {
+            let libs = resolve_shared_libs(program)?;
+            Ok(libs.into_iter().map(|lib| lib.0).collect())
+        });
+        match resolved {
+            Ok(resolved) => {
+                for lib in resolved {
+                    if !seen.insert(lib.clone()) {
+                        continue;
+                    }
+                    let mount = Mount::try_from((lib, StdMountLocation::UserSo))?;
+                    let Mount::Fs {
+                        host_path,
+                        sbx_path,
+                        ..
+                    } = &mount
+                    else {
+                        unreachable!("standard location mounts are binds");
+                    };
+                    match placed.entry(sbx_path.0.clone()) {
+                        Entry::Occupied(first) => {
+                            if !same_file(first.get(), &host_path.0) {
+                                return Err(MountError::Collision {
+                                    sbx_path: sbx_path.0.clone(),
+                                    first: first.get().clone(),
+                                    second: host_path.0.clone(),
+                                });
+                            }
+                        }
+                        Entry::Vacant(slot) => {
+                            slot.insert(host_path.0.clone());
+                            libs.push(mount);
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!(
+                "could not resolve shared libraries of {}: {e}",
+                host_path.0.display()
+            ),
+        }
+    }
+    Ok(libs)
+}
+
+/// Whether `a` and `b` resolve to the same file. Paths that can't be resolved only match
+/// themselves.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// The host path of the dynamic loader `program` asks for, falling back to [`INTERPRETER_HOST`]
+/// when it doesn't name one.
+fn interpreter_for(program: &Path) -> PathBuf {
+    match elf_interpreter(program) {
+        Ok(Some(interpreter)) => interpreter,
+        Ok(None) => PathBuf::from(INTERPRETER_HOST),
+        Err(e) => {
+            warn!(
+                "could not read the ELF interpreter of {}: {e}",
+                program.display()
+            );
+            PathBuf::from(INTERPRETER_HOST)
+        }
+    }
+}
+
+/// The essential mounts and symlinks every sandbox starts from, before the mounts of whatever
+/// runs in it.
+#[derive(Clone)]
+pub struct Profile {
+    mounts: Vec<Mount>,
+    symlinks: Vec<Symlink>,
+}
+
+/// The merged-`/usr` symlinks of [`Profile::minimal`], as `(dest, src)`.
+const STD_SYMLINKS: &[(&str, &str)] = &[
+    ("/lib", "/usr/lib"),
+    ("/lib64", "/usr/lib64"),
+    ("/bin", "/usr/bin"),
+    ("/sbin", "/usr/sbin"),
+];
+
+/// The host tools [`Profile::coreutils`] binds, when the host has them.
+const COREUTILS: &[&str] = &[
+    "cat", "chmod", "cp", "cut", "date", "dirname", "echo", "env", "false", "grep", "head", "ln",
+    "ls", "mkdir", "mv", "pwd", "readlink", "rm", "rmdir", "sed", "sort", "tail", "touch", "tr",
+    "true", "uname", "wc",
+];
+
+impl Profile {
+    pub fn new(mounts: Vec<Mount>, symlinks: Vec<Symlink>) -> Self {
+        Self { mounts, symlinks }
+    }
+
+    /// The FHS skeleton: empty executable directories, `/proc`, `/dev`, and the usual
+    /// `/lib`, `/lib64`, `/bin` and `/sbin` symlinks into `/usr`.
+    pub fn minimal() -> Self {
+        Self {
+            mounts: vec![
+                // essential directories, even if empty.
+                Mount::Touch {
+                    sbx_path: "/usr/sbin".into(),
+                    perms: None,
+                },
+                Mount::Touch {
+                    sbx_path: "/usr/bin".into(),
+                    perms: None,
+                },
+                // pseudo-filesystems most tools expect to exist.
+                Mount::Proc {
+                    sbx_path: "/proc".into(),
+                },
+                Mount::Dev {
+                    sbx_path: "/dev".into(),
+                },
+            ],
+            symlinks: STD_SYMLINKS
+                .iter()
+                .map(|&(dest, src)| Symlink {
+                    src: src.into(),
+                    dest: dest.into(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Leaves out the merged-`/usr` symlinks of [`Profile::minimal`], for a tree that only has
+    /// `/usr`. Programs then only find the dynamic loader once patched to load it from
+    /// `/usr/lib`, as the usual `/lib64/ld-linux-x86-64.so.2` is gone too.
+    pub fn without_std_symlinks(mut self) -> Self {
+        self.symlinks.retain(|symlink| {
+            !STD_SYMLINKS.iter().any(|&(dest, src)| {
+                symlink.dest.0 == Path::new(dest) && symlink.src.0 == Path::new(src)
+            })
+        });
+        self
+    }
+
+    /// `host_usr` bound read-only as the whole of `/usr`, along with `/proc`, `/dev` and the
+    /// merged-`/usr` symlinks, for bootstrapping from a reference system rather than a handful
+    /// of host programs and their libraries.
+    ///
+    /// Sandboxes see everything in `host_usr`, so builds are far less hermetic: they may pick
+    /// up any tool or library it has without declaring it. The loader and shared libraries come
+    /// from `host_usr` itself, and, as nothing can be created in the read-only tree, whatever
+    /// else is mounted under `/usr` has to exist in `host_usr` already.
+    pub fn reference_usr(host_usr: PathBuf) -> Self {
+        warn!(
+            "binding {} as /usr, sandboxes see everything in it and builds are not hermetic",
+            host_usr.display()
+        );
+        Self {
+            mounts: vec![
+                Mount::ro_bind(host_usr, "/usr"),
+                Mount::Proc {
+                    sbx_path: "/proc".into(),
+                },
+                Mount::Dev {
+                    sbx_path: "/dev".into(),
+                },
+            ],
+            symlinks: Self::minimal().symlinks,
+        }
+    }
+
+    /// Whether `/usr` is a read-only bind of a reference system, as from
+    /// [`Profile::reference_usr`].
+    fn has_reference_usr(&self) -> bool {
+        self.mounts.iter().any(|mount| {
+            matches!(mount, Mount::Fs { readonly: true, sbx_path, .. } if sbx_path.0 == Path::new("/usr"))
+        })
+    }
+
+    /// [`Profile::minimal`] with the host's basic file and text utilities in `/usr/bin`, for
+    /// interactive shells and scripts. Tools the host lacks are left out.
+    pub fn coreutils() -> Self {
+        let mut profile = Self::minimal();
+        for tool in COREUTILS {
+            let host = Path::new("/usr/bin").join(tool);
+            if !host.is_file() {
+                continue;
+            }
+            if let Ok(mount) = Mount::into_std(host, StdMountLocation::UserExe) {
+                profile.mounts.push(mount);
+            }
+        }
+        profile
+    }
+
+    pub fn with_mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Adds the `/etc/passwd` and `/etc/group` of [`user_database`], for tools such as `id`,
+    /// `tar` and `install -o` that look the sandbox's user up.
+    pub fn with_user(mut self, uid: u32, gid: u32) -> Self {
+        self.mounts.extend(user_database(uid, gid));
+        self
+    }
+
+    pub fn with_symlink(mut self, symlink: Symlink) -> Self {
+        self.symlinks.push(symlink);
+        self
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    pub fn symlinks(&self) -> &[Symlink] {
+        &self.symlinks
+    }
+
+    pub fn into_parts(self) -> (Vec<Mount>, Vec<Symlink>) {
+        (self.mounts, self.symlinks)
+    }
+}
+
+/// The name of the one user, and its group, in [`user_database`].
+const SBX_USER: &str = "builder";
+
+/// A minimal `/etc/passwd` and `/etc/group` holding a single `builder` user with `uid`, in a group
+/// of the same name with `gid`, whose home is the build directory.
+pub fn user_database(uid: u32, gid: u32) -> [Mount; 2] {
+    let passwd = format!("{SBX_USER}:x:{uid}:{gid}:{SBX_USER}:{SBX_BUILD_DIR}:/bin/sh\n");
+    let group = format!("{SBX_USER}:x:{gid}:\n");
+    [
+        Mount::File {
+            contents: passwd.into_bytes(),
+            sbx_path: "/etc/passwd".into(),
+            perms: Some(0o644),
+        },
+        Mount::File {
+            contents: group.into_bytes(),
+            sbx_path: "/etc/group".into(),
+            perms: Some(0o644),
+        },
+    ]
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::minimal()
+    }
+}
+
+/// Sets up the sandbox filesystem of `profile` around `mounts`, along with the shared libraries
+/// they need, unless `profile` has a [reference `/usr`](Profile::reference_usr) that brings its
+/// own.
+pub fn base_sandbox(
+    profile: &Profile,
+    interpreter: &Path,
+    mounts: impl IntoIterator<Item = Mount>,
+) -> Result<Bubblewrap, MountError> {
+    let mut builder = Bubblewrap::new();
+    let reference_usr = profile.has_reference_usr();
+    let (profile_mounts, profile_symlinks) = profile.clone().into_parts();
+    let mounts = expand_globs(profile_mounts.into_iter().chain(mounts).collect())?;
+    if reference_usr {
+        builder.add_mounts(mounts);
+        builder.add_symlinks(profile_symlinks);
+        return Ok(builder);
+    }
+
+    let libs = shared_lib_mounts(&mounts)?;
+    builder.add_mounts(mounts);
+    builder.add_mounts(libs);
+
+    // ld-linux, reachable from both /usr/lib and /usr/lib64 so that the path in the
+    // program's PT_INTERP resolves whichever of the two it names.
+    let interpreter_name = interpreter.file_name().unwrap_or_default();
+    let sbx_interpreter = Path::new(FHS_SO).join(interpreter_name);
+    builder.add_mount(Mount::Fs {
+        readonly: true,
+        try_bind: false,
+        host_path: interpreter.into(),
+        sbx_path: sbx_interpreter.clone().into(),
+    });
+
+    // bwrap doesn't create the directory a symlink goes in
+    builder.add_mount(Mount::Touch {
+        sbx_path: "/usr/lib64".into(),
+        perms: None,
+    });
+    builder.add_symlink(Symlink {
+        src: sbx_interpreter.into(),
+        dest: Path::new("/usr/lib64").join(interpreter_name).into(),
+    });
+    builder.add_symlinks(profile_symlinks);
+
+    Ok(builder)
+}
+
+/// Runs `proc` in a sandbox of `profile` with `mounts` and waits for it. With `dry_run`, the
+/// bwrap command line is printed instead and success is reported.
+fn launch_bubblewrap(
+    proc: &Path,
+    profile: &Profile,
+    mounts: impl IntoIterator<Item = Mount>,
+    dry_run: bool,
+) -> io::Result<ExitStatus> {
+    let mounts: Vec<Mount> = mounts.into_iter().collect();
+    let host_proc = mounts.iter().find_map(|mount| match mount {
+        Mount::Fs {
+            host_path,
+            sbx_path,
+            ..
+        } if sbx_path.0 == proc => Some(&host_path.0),
+        _ => None,
+    });
+    let interpreter = match host_proc {
+        Some(host_proc) => interpreter_for(host_proc),
+        None => PathBuf::from(INTERPRETER_HOST),
+    };
+
+    let sandbox = base_sandbox(profile, &interpreter, mounts)?
+        .with_program(proc.to_path_buf())
+        .with_inherit_env(false)
+        .with_unshare_net(true)
+        .with_die_with_parent(true)
+        .with_cap_drop_all()
+        .with_hostname(SBX_HOSTNAME)
+        .with_uid(SBX_UID)
+        .with_gid(SBX_GID);
+    if dry_run {
+        sandbox.validate();
+        println!("{}", sandbox.shell_command());
+        return Ok(ExitStatus::from_raw(0));
+    }
+
+    let bwrap = sandbox.bwrap();
+    let mut proc = sandbox.spawn()?;
+
+    let status = proc.wait()?;
+    info!("sandbox {}", describe_status(status));
+    events::record(&Event::SandboxExited {
+        exit_code: status.code(),
+        signal: status.signal(),
+    });
+    match userns_failure(&bwrap, status) {
+        Some(e) => Err(e),
+        None => Ok(status),
+    }
+}
+
+/// Settings that affect how projects are built, rather than what is built.
+#[derive(Default)]
+pub struct BuildOptions {
+    /// Print the sandboxed commands instead of running them.
+    pub dry_run: bool,
+    /// Let commands write straight to unpak's stdout and stderr, rather than prefixing each
+    /// line they print with the project and program. Needed for builds that want a terminal.
+    pub raw_output: bool,
+    /// How many more times to try a failed download.
+    pub fetch_retries: u32,
+    /// How long a single download may take.
+    pub fetch_timeout: Option<Duration>,
+    /// How long a build command may run, unless it sets its own timeout.
+    pub build_timeout: Option<Duration>,
+    /// Print how long each command took once a project is built.
+    pub timings: bool,
+    /// The only host paths builds may bind, if restricted. See [`allowed_paths`].
+    pub allowed_paths: Option<Vec<PathBuf>>,
+    /// Print build output and dry-run plans to stderr, leaving stdout to a [`BuildSummary`].
+    pub output_to_stderr: bool,
+    /// Leave generated build scripts behind, and point out the staging tree of a failed build,
+    /// printing their paths for inspection.
+    pub keep_temp: bool,
+    /// The `SOURCE_DATE_EPOCH` of every build, in seconds since the Unix epoch, instead of the
+    /// date of each project's source archive. Like the rest of the variables unpak sets, it
+    /// only reaches commands whose sandbox clears the environment rather than inheriting it,
+    /// as build sandboxes do.
+    pub source_date_epoch: Option<u64>,
+    /// Let build commands reach the host's network, rather than giving each sandbox a network
+    /// namespace of its own with nothing in it.
+    pub share_net: bool,
+    /// With `dry_run`, print the filesystem of each sandbox from
+    /// [`Bubblewrap::mount_report`] instead of its bwrap command line.
+    pub print_mounts: bool,
+    /// Only run these phases of each build, if any are given.
+    pub only_phases: Vec<Phase>,
+    /// Skip these phases of each build.
+    pub skip_phases: Vec<Phase>,
+    /// Fail builds that change files outside their staging tree, other than those the source
+    /// tree's `.unpakignore` lists.
+    pub audit: bool,
+    /// Expand variables that aren't set to nothing, rather than failing the build. See
+    /// [`expand_vars`].
+    pub allow_unset_vars: bool,
+    /// Skip the `check` commands of each project.
+    pub no_check: bool,
+    /// Leave the merged-`/usr` symlinks out of build sandboxes. See
+    /// [`Profile::without_std_symlinks`].
+    pub no_std_symlinks: bool,
+}
+
+impl BuildOptions {
+    /// Whether builds run `phase`, going by [`only_phases`](Self::only_phases) and
+    /// [`skip_phases`](Self::skip_phases).
+    pub fn runs_phase(&self, phase: Phase) -> bool {
+        (self.only_phases.is_empty() || self.only_phases.contains(&phase))
+            && !self.skip_phases.contains(&phase)
+    }
+
+    /// Whether builds run in full, so their results can be cached.
+    fn runs_every_phase(&self) -> bool {
+        self.only_phases.is_empty() && self.skip_phases.is_empty()
+    }
+
+    /// Prints a line of build output or of a dry-run plan.
+    fn print(&self, line: fmt::Arguments<'_>) {
+        progress::above(|| {
+            if self.output_to_stderr {
+                eprintln!("{line}");
+            } else {
+                println!("{line}");
+            }
+        })
+    }
+}
+
+/// Prints how long each command of the build of `id` took, slowest first.
+fn print_timings(
+    id: &ProjectId,
+    mut timings: Vec<(String, Duration)>,
+    total: Duration,
+    options: &BuildOptions,
+) {
+    timings.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+    // one write, so the tables of projects built in parallel don't interleave
+    let mut table = format!("timings for {id}:\n");
+    for (command, elapsed) in &timings {
+        table.push_str(&format!("  {:>9.2}s  {command}\n", elapsed.as_secs_f64()));
+    }
+    table.push_str(&format!("  {:>9.2}s  total", total.as_secs_f64()));
+    options.print(format_args!("{table}"));
+}
+
+/// How often `wait_timeout` checks whether the child has exited.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Waits for `child` to exit, killing it once `timeout` has passed. Returns `None` if it had to
+/// be killed.
+fn wait_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// How long to wait before retrying a download the first time.
+const FETCH_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Copies lines from `output` to unpak's stdout, or stderr with `to_stderr`, each starting with
+/// `prefix`. A line is written at once, so lines of concurrent builds don't mix.
+fn forward_prefixed(
+    output: impl Read + Send + 'static,
+    prefix: &str,
+    to_stderr: bool,
+) -> thread::JoinHandle<()> {
+    let prefix = prefix.to_owned();
+    thread::spawn(move || {
+        let mut output = BufReader::new(output);
+        let mut line = prefix.clone().into_bytes();
+        loop {
+            line.truncate(prefix.len());
+            match output.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("could not read build output: {e}");
+                    break;
+                }
+            }
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            let written = progress::above(|| {
+                if to_stderr {
+                    io::stderr().lock().write_all(&line)
+                } else {
+                    io::stdout().lock().write_all(&line)
+                }
+            });
+            if written.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BuildCmd {
+    program: PathBuf,
+    arguments: Vec<String>,
+    /// Directory to run the command in, relative to the project's base directory.
+    #[serde(default)]
+    workdir: Option<PathBuf>,
+    /// Extra environment variables for this command only, e.g. `[["CC", "gcc"]]`.
+    ///
+    /// These are added on top of the environment the command would otherwise get; they
+    /// never clear it.
+    #[serde(default)]
+    env: Vec<(String, String)>,
+    /// How many seconds the command may run before it is killed, overriding `--build-timeout`.
+    #[serde(
+        default,
+        rename = "timeout_secs",
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    timeout: Option<Duration>,
+    /// Only run the command on hosts of this architecture, named as Rust does, such as `x86_64`
+    /// or `aarch64`. Elsewhere it is skipped.
+    #[serde(default)]
+    arch: Option<String>,
+}
+
+impl BuildCmd {
+    /// Whether the command runs on this host, rather than being for another architecture.
+    pub fn runs_on_host(&self) -> bool {
+        self.arch
+            .as_deref()
+            .is_none_or(|arch| arch == std::env::consts::ARCH)
+    }
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs())
+        .serialize(serializer)
+}
+
+fn deserialize_secs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+}
+
+impl fmt::Display for BuildCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.to_string_lossy())?;
+        for arg in &self.arguments {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The stages of a build, which run in this order.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Unpack,
+    Configure,
+    Build,
+    Check,
+    Install,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Unpack => "unpack",
+            Phase::Configure => "configure",
+            Phase::Build => "build",
+            Phase::Check => "check",
+            Phase::Install => "install",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum BuildProcess {
+    /// Commands run in order, all in the `build` [`Phase`].
+    Cmds(Vec<BuildCmd>),
+    /// Commands grouped by [`Phase`], run a phase at a time in the order of phases, as in
+    ///
+    /// ```toml
+    /// [[build.Phased.configure]]
+    /// program = "./configure"
+    /// arguments = []
+    ///
+    /// [[build.Phased.check]]
+    /// program = "make"
+    /// arguments = ["check"]
+    /// ```
+    Phased(BTreeMap<Phase, Vec<BuildCmd>>),
+    /// A shell script, run by `shell` from the project's source tree.
+    Script { shell: PathBuf, body: String },
+    /// `./configure --prefix=<prefix> <configure_args>`, `make` and
+    /// `make install DESTDIR=<staging tree>`.
+    Autotools {
+        #[serde(default)]
+        configure_args: Vec<String>,
+    },
+    /// `cmake -S . -B build -DCMAKE_INSTALL_PREFIX=<prefix> -D<name>=<value>...`,
+    /// `cmake --build build` and `cmake --install build` with `DESTDIR` set to the staging tree.
+    CMake {
+        #[serde(default)]
+        defines: Vec<(String, String)>,
+    },
+    /// `make <targets>` and `make install DESTDIR=<staging tree> PREFIX=<prefix>`.
+    Make {
+        #[serde(default)]
+        targets: Vec<String>,
+    },
+}
+
+impl BuildProcess {
+    /// The commands the build runs for a project installed under `prefix`, or `None` for a
+    /// `Script`.
+    fn commands(&self, prefix: &Path) -> Option<Vec<BuildCmd>> {
+        let phases = self.phases(prefix)?;
+        Some(phases.into_iter().flat_map(|(_, cmds)| cmds).collect())
+    }
+
+    /// The commands of [`commands`](Self::commands), by phase in the order they run.
+    fn phases(&self, prefix: &Path) -> Option<Vec<(Phase, Vec<BuildCmd>)>> {
+        let cmd = |program: &str, arguments: Vec<String>| BuildCmd {
+            program: program.into(),
+            arguments,
+            workdir: None,
+            env: Vec::new(),
+            timeout: None,
+            arch: None,
+        };
+        let prefix = prefix.display();
+        let destdir = format!("DESTDIR={SBX_STAGE_DIR}");
+        let phases = match self {
+            BuildProcess::Cmds(cmds) => vec![(Phase::Build, cmds.clone())],
+            BuildProcess::Phased(phases) => phases
+                .iter()
+                .map(|(phase, cmds)| (*phase, cmds.clone()))
+                .collect(),
+            BuildProcess::Script { .. } => return None,
+            BuildProcess::Autotools { configure_args } => {
+                let mut configure = vec![format!("--prefix={prefix}")];
+                configure.extend(configure_args.iter().cloned());
+                vec![
+                    (Phase::Configure, vec![cmd("./configure", configure)]),
+                    (Phase::Build, vec![cmd("make", Vec::new())]),
+                    (
+                        Phase::Install,
+                        vec![cmd("make", vec!["install".to_owned(), destdir])],
+                    ),
+                ]
+            }
+            BuildProcess::CMake { defines } => {
+                let mut configure: Vec<String> =
+                    ["-S", ".", "-B", "build"].map(String::from).into();
+                configure.push(format!("-DCMAKE_INSTALL_PREFIX={prefix}"));
+                configure.extend(
+                    defines
+                        .iter()
+                        .map(|(name, value)| format!("-D{name}={value}")),
+                );
+                let mut install = cmd("cmake", vec!["--install".to_owned(), "build".to_owned()]);
+                install
+                    .env
+                    .push(("DESTDIR".to_owned(), SBX_STAGE_DIR.to_owned()));
+                vec![
+                    (Phase::Configure, vec![cmd("cmake", configure)]),
+                    (
+                        Phase::Build,
+                        vec![cmd("cmake", vec!["--build".to_owned(), "build".to_owned()])],
+                    ),
+                    (Phase::Install, vec![install]),
+                ]
+            }
+            BuildProcess::Make { targets } => vec![
+                (Phase::Build, vec![cmd("make", targets.clone())]),
+                (
+                    Phase::Install,
+                    vec![cmd(
+                        "make",
+                        vec!["install".to_owned(), destdir, format!("PREFIX={prefix}")],
+                    )],
+                ),
+            ],
+        };
+        Some(phases)
+    }
+}
+
+/// A uniquely named host directory that is removed along with its contents when dropped, unless
+/// it is kept.
+struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    fn new() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "unpak-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir(&path)?;
+        Ok(TempDir { path, keep: false })
+    }
+
+    /// Leaves the directory behind on drop when `keep` is set, logging where it is.
+    fn with_keep(mut self, keep: bool) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            info!("kept temporary directory {}", self.path.display());
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!("could not remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestFailure {
+    Read(io::Error),
+    /// Nothing was piped to `unpak build -`.
+    Empty,
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// The format couldn't be told from the extension and neither parser accepted the file.
+    Unrecognized {
+        toml: toml::de::Error,
+        json: serde_json::Error,
+    },
+    /// The `id` isn't a single path component, see [`ProjectId::is_file_name`].
+    BadId(ProjectId),
+    /// The `sha256` of an archive source isn't 64 lowercase hex digits.
+    BadChecksum(String),
+    /// The `rev` of a git source isn't a full commit hash.
+    BadRev(String),
+}
+
+/// A project manifest that couldn't be loaded.
+#[derive(Debug)]
+pub struct ManifestError {
+    path: PathBuf,
+    failure: Box<ManifestFailure>,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &*self.failure {
+            ManifestFailure::Read(e) => write!(f, "could not read manifest {path}: {e}"),
+            ManifestFailure::Empty => write!(f, "manifest {path} is empty"),
+            ManifestFailure::Toml(e) => write!(f, "invalid TOML manifest {path}: {e}"),
+            ManifestFailure::Json(e) => write!(f, "invalid JSON manifest {path}: {e}"),
+            ManifestFailure::Unrecognized { toml, json } => write!(
+                f,
+                "manifest {path} is neither TOML ({toml}) nor JSON ({json})"
+            ),
+            ManifestFailure::BadId(id) => write!(
+                f,
+                "manifest {path} has id {:?}, which must not be empty, `.`, `..` or contain `/`",
+                id.0
+            ),
+            ManifestFailure::BadChecksum(sha256) => write!(
+                f,
+                "manifest {path} has source sha256 {sha256:?}, which is not 64 lowercase hex digits"
+            ),
+            ManifestFailure::BadRev(rev) => write!(
+                f,
+                "manifest {path} has source rev {rev:?}, which is not a full commit hash of 40 or 64 hex digits"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &*self.failure {
+            ManifestFailure::Read(e) => Some(e),
+            ManifestFailure::Toml(e) => Some(e),
+            ManifestFailure::Json(e) => Some(e),
+            ManifestFailure::Empty
+            | ManifestFailure::Unrecognized { .. }
+            | ManifestFailure::BadId(_)
+            | ManifestFailure::BadChecksum(_)
+            | ManifestFailure::BadRev(_) => None,
+        }
+    }
+}
+
+/// A problem with a manifest found by [`SourceProject::validate`].
+#[derive(Debug)]
+pub enum ValidationError {
+    EmptyId,
+    /// The project's id is in its `bdeps` or `rdeps`, named by `list`.
+    DependsOnItself {
+        list: &'static str,
+    },
+    /// The build has no commands at all.
+    NothingToBuild,
+    /// A build command runs this absolute path, which doesn't exist on the host.
+    MissingProgram(PathBuf),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyId => f.write_str("`id` is empty"),
+            ValidationError::DependsOnItself { list } => {
+                write!(f, "`{list}` lists the project itself")
+            }
+            ValidationError::NothingToBuild => f.write_str("`build` has no commands"),
+            ValidationError::MissingProgram(program) => {
+                write!(f, "{} does not exist on this host", program.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ManifestFormat {
+    Toml,
+    Json,
+}
+
+/// The manifest path that stands for standard input.
+const STDIN_MANIFEST: &str = "-";
+
+/// Loads a project manifest, choosing the format from the file extension.
+///
+/// Files with an unknown extension are tried as TOML first, then as JSON. The
+/// project's base directory is set to the directory containing the manifest.
+pub fn load_manifest(path: &Path) -> Result<SourceProject, ManifestError> {
+    load_manifest_as(path, None)
+}
+
+/// Loads a project manifest like [`load_manifest`], but in `format` if one is given.
+///
+/// A `path` of `-` reads the manifest from standard input instead, as TOML unless `format` says
+/// otherwise. Its base directory is the current directory.
+pub fn load_manifest_as(
+    path: &Path,
+    format: Option<ManifestFormat>,
+) -> Result<SourceProject, ManifestError> {
+    let from_stdin = path == Path::new(STDIN_MANIFEST);
+    let error = |failure| ManifestError {
+        path: if from_stdin {
+            PathBuf::from("<stdin>")
+        } else {
+            path.to_path_buf()
+        },
+        failure: Box::new(failure),
+    };
+    let text = if from_stdin {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| error(ManifestFailure::Read(e)))?;
+        if text.trim().is_empty() {
+            return Err(error(ManifestFailure::Empty));
+        }
+        text
+    } else {
+        std::fs::read_to_string(path).map_err(|e| error(ManifestFailure::Read(e)))?
+    };
+
+    let format = format.or(match path.extension().and_then(OsStr::to_str) {
+        Some("toml") => Some(ManifestFormat::Toml),
+        Some("json") => Some(ManifestFormat::Json),
+        _ if from_stdin => Some(ManifestFormat::Toml),
+        _ => None,
+    });
+    let mut project: SourceProject = match format {
+        Some(ManifestFormat::Toml) => {
+            toml::from_str(&text).map_err(|e| error(ManifestFailure::Toml(e)))
+        }
+        Some(ManifestFormat::Json) => {
+            serde_json::from_str(&text).map_err(|e| error(ManifestFailure::Json(e)))
+        }
+        None => toml::from_str(&text).or_else(|toml| {
+            serde_json::from_str(&text)
+                .map_err(|json| error(ManifestFailure::Unrecognized { toml, json }))
+        }),
+    }?;
+    // ids become file names, so one like `..` would reach outside unpak's directories
+    if !project.id.is_file_name() {
+        return Err(error(ManifestFailure::BadId(project.id)));
+    }
+    // and so do checksums and commits, of the sources fetched
+    match &project.source {
+        Some(SourceSpec::Archive(archive)) if !archive.checksum_is_valid() => {
+            return Err(error(ManifestFailure::BadChecksum(archive.sha256.clone())));
+        }
+        Some(SourceSpec::Git(git)) if !git.rev_is_valid() => {
+            return Err(error(ManifestFailure::BadRev(git.rev.clone())));
+        }
+        _ => {}
+    }
+
+    project.base_dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    project.resolve_local_sources();
+    Ok(project)
+}
+
+/// Runs an interactive shell in a sandbox with the host's basic utilities, and the merged-`/usr`
+/// symlinks unless `no_std_symlinks`.
+pub fn launch_shell(no_std_symlinks: bool, dry_run: bool) -> io::Result<ExitStatus> {
+    // shared libraries are discovered with ldd by launch_bubblewrap
+    let bash = Mount::into_std("/usr/bin/bash", StdMountLocation::UserExe)?;
+
+    let mut profile = Profile::coreutils().with_user(SBX_UID, SBX_GID);
+    if no_std_symlinks {
+        profile = profile.without_std_symlinks();
+    }
+    launch_bubblewrap(Path::new("/usr/bin/bash"), &profile, [bash], dry_run)
+}
+
+/// Whether `target` names a manifest file, `-` for stdin, rather than a project id.
+fn is_manifest_path(target: &Path) -> bool {
+    target == Path::new(STDIN_MANIFEST)
+        || target.exists()
+        || target.components().count() > 1
+        || matches!(
+            target.extension().and_then(OsStr::to_str),
+            Some("toml" | "json")
+        )
+}
+
+/// Builds `target` after its dependencies, which come from `registry`.
+/// `target` is the path of a manifest, `-` for stdin, or the id of a project in the registry.
+///
+/// The versions of dependencies are taken from the project's lockfile. Without one, or with
+/// `update`, they are resolved again and the lockfile is rewritten. Projects from the registry
+/// have no lockfile, as the registry has only one version of each. Up to `jobs` projects are
+/// built at once. Returns what happened to each project; a failed build's is in its error.
+pub fn build_project(
+    target: &Path,
+    format: Option<ManifestFormat>,
+    mut registry: HashMap<ProjectId, SourceProject>,
+    update: bool,
+    jobs: usize,
+    options: &BuildOptions,
+) -> Result<BuildSummary, UnpakError> {
+    let from_registry = !is_manifest_path(target);
+    let project = if from_registry {
+        let id = ProjectId::from(&*target.to_string_lossy());
+        registry.remove(&id).ok_or(PlanError::Unknown(id))?
+    } else {
+        load_manifest_as(target, format)?
+    };
+    project.validate().map_err(|errors| UnpakError::Invalid {
+        manifest: target.display().to_string(),
+        errors,
+    })?;
+    let lock_path = lock::lockfile_path(&project);
+    let locked = if update || from_registry {
+        None
+    } else {
+        lock::read_lockfile(&lock_path).map_err(UnpakError::io(format!(
+            "could not read {}",
+            lock_path.display()
+        )))?
+    };
+    let plan = match locked {
+        Some(locked) => locked_plan(&project, &registry, locked)?,
+        None => {
+            let plan = build_plan(&project, &registry)?;
+            if !options.dry_run && !from_registry {
+                let resolved: Vec<&SourceProject> = plan
+                    .iter()
+                    .map(|id| registry.get(id).unwrap_or(&project))
+                    .collect();
+                lock::write_lockfile(&lock_path, &resolved).map_err(UnpakError::io(format!(
+                    "could not write {}",
+                    lock_path.display()
+                )))?;
+            }
+            plan
+        }
+    };
+    registry.insert(project.id.clone(), project);
+    let summary = build_all(&plan, &registry, jobs, &FsCache::open_default(), options)?;
+    Ok(summary)
+}
+
+/// Points the cache and state directories at a scratch directory, the same for every test of
+/// the process, so tests never touch the user's. Tests using them need ids of their own.
+#[cfg(test)]
+fn test_dirs() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("unpak-test-{}", std::process::id()));
+        cache::set_cache_dir(dir.join("cache"));
+        std::env::set_var("XDG_STATE_HOME", dir.join("state"));
+        dir
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// The arguments `sandbox` would pass to bwrap, as strings.
+    fn args(sandbox: &Bubblewrap) -> Vec<String> {
+        sandbox
+            .command_line()
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Where `flag` followed by `operands` appears in `args`, if it does.
+    fn position(args: &[String], flag: &str, operands: &[&str]) -> Option<usize> {
+        args.windows(operands.len() + 1)
+            .position(|window| window[0] == flag && window[1..] == *operands)
+    }
+
+    #[test]
+    fn binds_a_sandbox_path_once() {
+        let sandbox = Bubblewrap::new()
+            .with_mount(Mount::ro_bind("/usr/lib/one.so", "/usr/lib/libfoo.so"))
+            .with_mount(Mount::ro_bind("/opt/two.so", "/usr/lib/libfoo.so"))
+            .with_mount(Mount::Touch {
+                sbx_path: "/usr/lib/libfoo.so".into(),
+                perms: None,
+            });
+        let args = args(&sandbox);
+        let binds: Vec<&String> = args
+            .iter()
+            .filter(|arg| *arg == "/usr/lib/libfoo.so")
+            .collect();
+        assert_eq!(binds.len(), 1);
+        assert!(position(
+            &args,
+            "--ro-bind",
+            &["/usr/lib/one.so", "/usr/lib/libfoo.so"]
+        )
+        .is_some());
+        assert!(!args.iter().any(|arg| arg == "--dir"));
+    }
+
+    #[test]
+    fn optional_binds_use_the_try_flags() {
+        let sandbox = Bubblewrap::new()
+            .with_mount(Mount::ro_bind("/usr/lib/a.so", "/usr/lib/a.so"))
+            .with_mount(Mount::ro_bind("/usr/lib/b.so", "/usr/lib/b.so").optional())
+            .with_mount(Mount::rw_bind("/tmp/c", "/c").optional());
+        let args = args(&sandbox);
+        assert!(position(&args, "--ro-bind", &["/usr/lib/a.so", "/usr/lib/a.so"]).is_some());
+        assert!(position(&args, "--ro-bind-try", &["/usr/lib/b.so", "/usr/lib/b.so"]).is_some());
+        assert!(position(&args, "--bind-try", &["/tmp/c", "/c"]).is_some());
+    }
+
+    #[test]
+    fn leaves_scripts_unpatched() {
+        let dir = TempDir::new().unwrap();
+        let script = dir.path().join("script");
+        std::fs::write(&script, "#!/bin/sh\necho hi\n").unwrap();
+        // patchelf would fail on it, if it were even installed
+        patch_noncompliant(&script).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&script).unwrap(),
+            "#!/bin/sh\necho hi\n"
+        );
+    }
+
+    #[test]
+    fn refuses_standard_mounts_without_a_file_name() {
+        for path in ["/", "/usr/lib/.."] {
+            let mount = Mount::try_from((path, StdMountLocation::UserSo));
+            assert!(matches!(mount, Err(MountError::NoFileName(_))), "{path}");
+        }
+    }
+
+    #[test]
+    fn refuses_libraries_sharing_a_file_name() {
+        test_dirs();
+        // the test binary links libc, which would land where another libc.so.6 already is
+        let dir = TempDir::new().unwrap();
+        let other = dir.path().join("libc.so.6");
+        std::fs::write(&other, "not libc").unwrap();
+        let mounts = [
+            Mount::ro_bind(std::env::current_exe().unwrap(), "/usr/bin/unpak-test"),
+            Mount::ro_bind(&other, "/usr/lib/libc.so.6"),
+        ];
+        match shared_lib_mounts(&mounts) {
+            Err(MountError::Collision {
+                sbx_path, first, ..
+            }) => {
+                assert_eq!(sbx_path, Path::new("/usr/lib/libc.so.6"));
+                assert_eq!(first, other);
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("libc was mounted twice"),
+        }
+    }
+
+    #[test]
+    fn changes_into_the_sandbox_directory_given() {
+        assert!(!args(&Bubblewrap::new()).iter().any(|arg| arg == "--chdir"));
+        let sandbox = Bubblewrap::new().with_chdir(PathBuf::from(SBX_BUILD_DIR));
+        assert!(position(&args(&sandbox), "--chdir", &[SBX_BUILD_DIR]).is_some());
+    }
+
+    #[test]
+    fn creates_parents_before_what_is_mounted_in_them() {
+        let sandbox = Bubblewrap::new()
+            .with_mount(Mount::ro_bind("/usr/lib/foo/bar.so", "/usr/lib/foo/bar.so"))
+            .with_mount(Mount::Touch {
+                sbx_path: "/usr/lib/foo".into(),
+                perms: None,
+            });
+        let args = args(&sandbox);
+        let parent = position(&args, "--dir", &["/usr/lib/foo"]).unwrap();
+        let child = position(
+            &args,
+            "--ro-bind",
+            &["/usr/lib/foo/bar.so", "/usr/lib/foo/bar.so"],
+        );
+        assert!(parent < child.unwrap());
+    }
+
+    #[test]
+    fn sets_perms_for_the_next_directory_only() {
+        let sandbox = Bubblewrap::new()
+            .with_mount(Mount::Touch {
+                sbx_path: "/private".into(),
+                perms: Some(0o700),
+            })
+            .with_mount(Mount::Touch {
+                sbx_path: "/public".into(),
+                perms: None,
+            });
+        let args = args(&sandbox);
+        let perms = position(&args, "--perms", &["0700"]).unwrap();
+        assert_eq!(position(&args, "--dir", &["/private"]), Some(perms + 2));
+        assert_eq!(args.iter().filter(|arg| *arg == "--perms").count(), 1);
+    }
+
+    #[test]
+    fn binds_same_path_mounts_where_they_are_on_the_host() {
+        let sandbox = Bubblewrap::new()
+            .with_same_path("/nix/store", true)
+            .with_same_path("/var/tmp/work", false);
+        let args = args(&sandbox);
+        assert!(position(&args, "--ro-bind", &["/nix/store", "/nix/store"]).is_some());
+        assert!(position(&args, "--bind", &["/var/tmp/work", "/var/tmp/work"]).is_some());
+    }
+
+    #[test]
+    fn setting_a_variable_clears_the_inherited_environment() {
+        let sandbox = Bubblewrap::new().with_envvar("FOO".into(), "bar".into());
+        let args = args(&sandbox);
+        let cleared = position(&args, "--clearenv", &[]).unwrap();
+        assert!(cleared < position(&args, "--setenv", &["FOO", "bar"]).unwrap());
+    }
+
+    #[test]
+    fn sets_path_from_the_mounted_executables() {
+        let cleared = || Bubblewrap::new().with_inherit_env(false);
+        let path = |sandbox: &Bubblewrap| {
+            let args = args(sandbox);
+            position(&args, "--setenv", &["PATH"]).map(|i| args[i + 2].clone())
+        };
+        assert_eq!(path(&cleared()).as_deref(), Some("/usr/bin:/usr/sbin"));
+
+        let dir = TempDir::new().unwrap();
+        let tool = dir.path().join("tool");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let sandbox = cleared().with_mount(Mount::ro_bind(&tool, "/opt/foo/bin/tool"));
+        assert_eq!(
+            path(&sandbox).as_deref(),
+            Some("/usr/bin:/usr/sbin:/opt/foo/bin")
+        );
+
+        assert_eq!(path(&cleared().with_path("/bin")).as_deref(), Some("/bin"));
+        // an inherited environment keeps its own PATH
+        assert_eq!(path(&Bubblewrap::new()), None);
+    }
+
+    #[test]
+    fn tells_signals_from_exit_codes() {
+        let status = std::process::Command::new("sh")
+            .args(["-c", "kill -KILL $$"])
+            .status()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert_eq!(describe_status(status), "killed by signal SIGKILL (9)");
+        assert_eq!(
+            describe_status(ExitStatus::from_raw(3 << 8)),
+            "exited with code 3"
+        );
+
+        assert!(BuildFailure::Signal(libc::SIGKILL).is_retryable());
+        assert!(!BuildFailure::Signal(libc::SIGSEGV).is_retryable());
+        assert!(!BuildFailure::Exit(ExitStatus::from_raw(1 << 8)).is_retryable());
+    }
+
+    #[test]
+    fn warns_about_the_terminal_only_when_there_is_one() {
+        let escape = "sandbox escape may be possible because process can control terminal.";
+        let job_control = "setsid will break job control.";
+        let build = || Bubblewrap::new().with_pipe_output(true);
+
+        // builds pipe their output and run in a session of their own
+        let sandbox = build().with_new_session(true);
+        assert!(sandbox.terminal_warnings(true, true).is_empty());
+        assert_eq!(build().terminal_warnings(true, true), [escape]);
+        assert!(build().terminal_warnings(false, false).is_empty());
+
+        let shell = Bubblewrap::new();
+        assert_eq!(shell.terminal_warnings(true, false), [escape]);
+        let shell = shell.with_new_session(true);
+        assert_eq!(shell.terminal_warnings(true, true), [job_control]);
+        assert!(shell.terminal_warnings(false, false).is_empty());
+    }
+
+    #[test]
+    fn user_database_holds_one_valid_user() {
+        let [Mount::File {
+            contents: passwd,
+            sbx_path: passwd_path,
+            ..
+        }, Mount::File {
+            contents: group,
+            sbx_path: group_path,
+            ..
+        }] = user_database(1000, 100)
+        else {
+            panic!("the user database is made of files");
+        };
+        assert_eq!(passwd_path.0, Path::new("/etc/passwd"));
+        assert_eq!(group_path.0, Path::new("/etc/group"));
+
+        let passwd = String::from_utf8(passwd).unwrap();
+        let lines: Vec<&str> = passwd.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let fields: Vec<&str> = lines[0].split(':').collect();
+        assert_eq!(fields.len(), 7);
+        assert_eq!(fields[..4], [SBX_USER, "x", "1000", "100"]);
+        assert!(Path::new(fields[5]).is_absolute() && Path::new(fields[6]).is_absolute());
+
+        let group = String::from_utf8(group).unwrap();
+        assert_eq!(group, format!("{SBX_USER}:x:100:\n"));
+    }
+
+    #[test]
+    fn resolves_local_sources_against_the_manifest() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("foo.tar.gz");
+        std::fs::write(&archive, "").unwrap();
+        let recipes = dir.path().join("recipes");
+        std::fs::create_dir(&recipes).unwrap();
+        let sha256 = "0".repeat(64);
+        let manifest = |url: &str| {
+            let path = recipes.join("foo.toml");
+            std::fs::write(
+                &path,
+                format!(
+                    "id = \"foo\"\nrdeps = []\nbdeps = []\n\
+                     source = {{ url = \"{url}\", sha256 = \"{sha256}\" }}\n\
+                     [[build.Cmds]]\nprogram = \"true\"\narguments = []\n"
+                ),
+            )
+            .unwrap();
+            load_manifest(&path).unwrap()
+        };
+
+        let project = manifest("../foo.tar.gz");
+        assert_eq!(project.base_dir, recipes);
+        let location = project.source.as_ref().unwrap().location().to_owned();
+        let path = location.strip_prefix("file://").unwrap();
+        assert_eq!(
+            Path::new(path).canonicalize().unwrap(),
+            archive.canonicalize().unwrap()
+        );
+
+        let url = "https://example.com/foo.tar.gz";
+        assert_eq!(manifest(url).source.unwrap().location(), url);
+    }
+
+    #[test]
+    fn links_the_std_directories_unless_asked_not_to() {
+        let interpreter = Path::new(INTERPRETER_HOST);
+        let loader = "/usr/lib64/ld-linux-x86-64.so.2";
+        let linked = args(&base_sandbox(&Profile::minimal(), interpreter, []).unwrap());
+        for (dest, src) in STD_SYMLINKS {
+            assert!(
+                position(&linked, "--symlink", &[src, dest]).is_some(),
+                "{dest}"
+            );
+        }
+        let lib64 = position(&linked, "--dir", &["/usr/lib64"]).unwrap();
+        let symlink = position(
+            &linked,
+            "--symlink",
+            &["/usr/lib/ld-linux-x86-64.so.2", loader],
+        );
+        assert!(lib64 < symlink.unwrap());
+
+        let profile = Profile::minimal().without_std_symlinks();
+        let unlinked = args(&base_sandbox(&profile, interpreter, []).unwrap());
+        for (dest, src) in STD_SYMLINKS {
+            assert!(
+                position(&unlinked, "--symlink", &[src, dest]).is_none(),
+                "{dest}"
+            );
+        }
+        // the loader is still reachable where programs look for it
+        assert!(position(
+            &unlinked,
+            "--symlink",
+            &["/usr/lib/ld-linux-x86-64.so.2", loader]
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn expands_variables_from_the_command_environment() {
+        let env = [
+            ("PREFIX".to_owned(), "/usr".to_owned()),
+            ("JOBS".to_owned(), "1".to_owned()),
+            ("JOBS".to_owned(), "4".to_owned()),
+        ];
+        let expand = |text| expand_vars(text, &env, false).unwrap();
+        assert_eq!(expand("--prefix=${PREFIX}/local"), "--prefix=/usr/local");
+        assert_eq!(expand("$PREFIX/bin:$JOBS"), "/usr/bin:4");
+        assert_eq!(expand("cost: $$5, $$PREFIX"), "cost: $5, $PREFIX");
+        assert_eq!(expand("$(nproc) $1 $"), "$(nproc) $1 $");
+
+        assert!(matches!(
+            expand_vars("${HOME}", &env, false),
+            Err(ExpandError::Unset(name)) if name == "HOME"
+        ));
+        assert_eq!(expand_vars("a${HOME}b", &env, true).unwrap(), "ab");
+        assert!(matches!(
+            expand_vars("${PREFIX", &env, false),
+            Err(ExpandError::Unterminated)
+        ));
+    }
+
+    #[test]
+    fn command_env_is_added_to_the_build_env() {
+        let project: SourceProject = toml::from_str(
+            "id = \"foo\"\nrdeps = []\nbdeps = []\n\
+             [[build.Cmds]]\nprogram = \"make\"\narguments = []\n\
+             env = [[\"CC\", \"clang\"], [\"UNPAK_PREFIX\", \"/opt/foo\"]]\n\
+             [[build.Cmds]]\nprogram = \"make\"\narguments = [\"install\"]\n",
+        )
+        .unwrap();
+        let BuildProcess::Cmds(cmds) = &project.build else {
+            panic!("the manifest lists commands");
+        };
+        let options = BuildOptions::default();
+        let pairs = |env: &[(String, String)]| -> Vec<String> {
+            env.iter()
+                .map(|(id, value)| format!("{id}={value}"))
+                .collect()
+        };
+
+        let env = project.build_env(&cmds[0], &options);
+        assert_eq!(
+            pairs(&env),
+            [
+                "UNPAK_DESTDIR=/stage",
+                "UNPAK_PREFIX=/usr",
+                "CC=clang",
+                "UNPAK_PREFIX=/opt/foo",
+            ]
+        );
+        // the last one wins
+        assert_eq!(
+            expand_vars("$UNPAK_PREFIX", &env, false).unwrap(),
+            "/opt/foo"
+        );
+
+        let env = project.build_env(&cmds[1], &options);
+        assert_eq!(pairs(&env), ["UNPAK_DESTDIR=/stage", "UNPAK_PREFIX=/usr"]);
+    }
+}
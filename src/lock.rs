@@ -0,0 +1,108 @@
+//! Lockfiles pinning the dependencies a project was resolved to.
+//!
+//! `unpak.lock` sits next to a project's manifest and lists every project of its build plan, in
+//! build order, with the version it resolved to:
+//!
+//! ```toml
+//! [[project]]
+//! id = "org.gnu.make"
+//! version = "4.4.1"
+//! ```
+
+use crate::{ProjectId, SourceProject};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const LOCKFILE_NAME: &str = "unpak.lock";
+
+/// A project as it was resolved. Projects without a version are locked without one.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LockedProject {
+    pub id: ProjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    project: Vec<LockedProject>,
+}
+
+/// Where the lockfile of `project` lives.
+pub fn lockfile_path(project: &SourceProject) -> PathBuf {
+    project.base_dir.join(LOCKFILE_NAME)
+}
+
+/// Records `plan`, in order, as the resolved dependencies in the lockfile at `path`.
+pub fn write_lockfile(path: &Path, plan: &[&SourceProject]) -> io::Result<()> {
+    let lockfile = Lockfile {
+        project: plan
+            .iter()
+            .map(|project| LockedProject {
+                id: project.id.clone(),
+                version: project.version.clone(),
+            })
+            .collect(),
+    };
+    let text =
+        toml::to_string(&lockfile).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    std::fs::write(path, text)
+}
+
+/// Reads the lockfile at `path`, or `None` if there isn't one.
+pub fn read_lockfile(path: &Path) -> io::Result<Option<Vec<LockedProject>>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let lockfile: Lockfile = toml::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+    Ok(Some(lockfile.project))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[test]
+    fn round_trips_the_plan_in_order() {
+        let project = |id: &str, version: Option<&str>| -> SourceProject {
+            let version = version.map_or(String::new(), |v| format!("version = \"{v}\"\n"));
+            toml::from_str(&format!(
+                "id = \"{id}\"\n{version}rdeps = []\nbdeps = []\n\
+                 [[build.Cmds]]\nprogram = \"true\"\narguments = []\n"
+            ))
+            .unwrap()
+        };
+        let make = project("org.gnu.make", Some("4.4.1"));
+        let local = project("local", None);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(LOCKFILE_NAME);
+        assert_eq!(read_lockfile(&path).unwrap(), None);
+
+        write_lockfile(&path, &[&make, &local]).unwrap();
+        let locked = read_lockfile(&path).unwrap().unwrap();
+        assert_eq!(
+            locked,
+            [
+                LockedProject {
+                    id: ProjectId::from("org.gnu.make"),
+                    version: Some(Version::new(4, 4, 1)),
+                },
+                LockedProject {
+                    id: ProjectId::from("local"),
+                    version: None,
+                },
+            ]
+        );
+    }
+}
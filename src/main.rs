@@ -1,515 +1,493 @@
-//! unpak, the modern package manager
-//! unpak needs a bootstrap GCC to compile the sandboxed-GCC
-//! |bootstrap GCC and programs| -> |stage1 GCC| -> |stage2 GCC| -> coreutils and build deps, etc
-//! stage1 is cross-compiled by the bootstrapper, and
-//! stage2 is compiled by stage1 to ensure full sandboxing.
-
-use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
-use std::ffi::{OsStr, OsString};
-use std::path::Path;
-use std::process::{Child, Stdio};
-use std::{io, io::ErrorKind, path::PathBuf, process::Command};
-
-// com.github.osten.unpak
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
-struct ProjectId(String);
-
-impl From<&str> for ProjectId {
-    fn from(other: &str) -> Self {
-        ProjectId(other.to_string())
-    }
-}
-
-impl From<String> for ProjectId {
-    fn from(other: String) -> Self {
-        ProjectId(other)
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-struct SourceProject {
-    id: ProjectId,
-    build: BuildProcess,
-    rdeps: Vec<ProjectId>,
-    bdeps: Vec<ProjectId>,
+//! The `unpak` command line. Everything it does is implemented by the library, see [`unpak`].
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::{warn, Level, LevelFilter};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use unpak::config::{self, Config};
+use unpak::error::UnpakError;
+use unpak::graph::GraphFormat;
+use unpak::{
+    clean, doctor, events, graph, info, install, interrupt, progress, registry, remove, state,
+};
+use unpak::{BuildOptions, ManifestFormat, Phase, ProjectId, SourceProject};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum BuildOutput {
+    /// Only the build output and log
+    Human,
+    /// A JSON summary of every project once the build is done
+    Json,
 }
 
-#[allow(dead_code)]
-impl SourceProject {
-    pub fn build(&self) {
-        println!("[unpak] building project...");
-        match &self.build {
-            BuildProcess::Cmds(cmds) => {
-                for cmd in cmds {
-                    println!(
-                        "[unpak] executing '{} {}'",
-                        cmd.program.to_string_lossy(),
-                        cmd.arguments.join(" ")
-                    );
-                    // Execute command
-                    Command::new(cmd.program.as_os_str())
-                        .args(&cmd.arguments)
-                        .spawn()
-                        .expect("failed to spawn command")
-                        .wait()
-                        .unwrap();
-                }
-            }
-        }
-    }
-}
-const INTERPRETER_HOST: &str = "/lib64/ld-linux-x86-64.so.2";
-const SBX_LD_LINUX: &str = "/usr/lib/ld-linux-x86-64.so.2";
+#[derive(Subcommand, Debug)]
+enum Action {
+    Build {
+        /// The project manifest file, `-` to read it from stdin, or the id of a project in the
+        /// registry
+        project: PathBuf,
 
-#[allow(dead_code)]
-fn patch_noncompliant(program: &Path) {
-    let mut command = Command::new("patchelf");
-    command.args(["--set-interpreter", SBX_LD_LINUX]);
-    command.arg(program);
+        /// The format of the manifest, instead of telling from its extension
+        #[arg(long, value_enum)]
+        format: Option<ManifestFormat>,
+
+        /// How many projects to build at once [default: 1]
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Resolve dependencies again instead of using the versions in unpak.lock
+        #[arg(long)]
+        update: bool,
+
+        /// How many times to retry a source download that failed
+        #[arg(long, default_value_t = 3, value_name = "N")]
+        fetch_retries: u32,
+
+        /// Give up on a source download after this many seconds
+        #[arg(long, value_name = "SECS")]
+        fetch_timeout: Option<u64>,
+
+        /// Kill build commands that run for more than this many seconds
+        #[arg(long, value_name = "SECS")]
+        build_timeout: Option<u64>,
+
+        /// Print how long each build command took
+        #[arg(long)]
+        timings: bool,
+
+        /// Only let builds bind host paths under the system directories, unpak's cache, the
+        /// temporary directory, the project itself and --allow-path
+        #[arg(long)]
+        restrict_mounts: bool,
+
+        /// Another host path builds may bind with --restrict-mounts
+        #[arg(long, value_name = "PATH", requires = "restrict_mounts")]
+        allow_path: Vec<PathBuf>,
+
+        /// Let builds reach the host's network, rather than giving them a network of their own
+        #[arg(long, overrides_with = "unshare_net")]
+        share_net: bool,
+
+        /// Cut builds off from the host's network, even if the config file shares it [default]
+        #[arg(long, overrides_with = "share_net")]
+        unshare_net: bool,
+
+        /// Don't prefix the output of build commands, so they can use the terminal directly
+        #[arg(long)]
+        raw_output: bool,
+
+        /// Keep generated build scripts and show where the staging tree of a failed build is
+        #[arg(long)]
+        keep_temp: bool,
+
+        /// The SOURCE_DATE_EPOCH of every build, in seconds since 1970, instead of the date of
+        /// each source archive
+        #[arg(long, value_name = "SECS")]
+        source_date: Option<u64>,
+
+        /// Print the mounts and symlinks each build command's sandbox would get, instead of
+        /// running anything
+        #[arg(long)]
+        print_mounts: bool,
+
+        /// Only run this phase of each build; may be repeated
+        #[arg(long, value_enum, value_name = "PHASE")]
+        only_phase: Vec<Phase>,
+
+        /// Skip this phase of each build; may be repeated
+        #[arg(long, value_enum, value_name = "PHASE")]
+        skip_phase: Vec<Phase>,
+
+        /// Fail builds that write anywhere but their staging tree, such as into the source tree
+        /// outside what its .unpakignore lists
+        #[arg(long)]
+        audit: bool,
+
+        /// Don't run the checks projects define for after their build
+        #[arg(long)]
+        no_check: bool,
+
+        /// Expand variables that build commands use but don't set to nothing, instead of failing
+        #[arg(long)]
+        allow_unset_vars: bool,
+
+        /// Leave /lib, /lib64, /bin and /sbin out of build sandboxes, rather than linking them
+        /// to their /usr counterparts
+        #[arg(long)]
+        no_std_symlinks: bool,
+
+        /// How to report the outcome of each project; JSON goes to stdout, and build output to
+        /// stderr instead
+        #[arg(long, value_enum, default_value_t = BuildOutput::Human)]
+        output: BuildOutput,
+    },
+    /// Launch a shell in a minimal sandbox
+    Shell {
+        /// Leave /lib, /lib64, /bin and /sbin out, rather than linking them to their /usr
+        /// counterparts
+        #[arg(long)]
+        no_std_symlinks: bool,
+    },
+    /// List the projects built or installed
+    List,
+    /// Copy what the last build of a project staged into a root directory
+    Install {
+        /// The id of the project
+        id: ProjectId,
+
+        /// The directory to install under, such as `/` for the host itself
+        #[arg(long, value_name = "DIR")]
+        root: PathBuf,
+    },
+    /// Delete the files a project installed, except those another project has too
+    Remove {
+        /// The id of the project
+        id: ProjectId,
+    },
+    /// Find projects in the registry by id
+    Search {
+        /// Part of the project id to look for
+        query: String,
+    },
+    /// Print the dependency graph of a project
+    Graph {
+        /// The project manifest file
+        project: PathBuf,
 
-    let mut proc = match command.spawn() {
-        Ok(proc) => proc,
-        Err(e) => {
-            match e.kind() {
-                ErrorKind::NotFound => {
-                    eprintln!("error: patchelf not found. Is patchelf installed?")
-                }
-                _ => eprintln!("error: unknown error: {e:?}"),
-            };
-            return;
-        }
-    };
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Show what a project manifest describes and what it depends on
+    Info {
+        /// The project manifest file
+        project: PathBuf,
 
-    let exit = proc.wait().unwrap();
-    eprintln!("[unpak] patchelf exited with code {exit}");
+        /// Print JSON instead of a report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that this host can run unpak's sandboxes
+    Doctor,
+    /// Point programs at the sandbox's dynamic loader, so they can run in a sandbox
+    Patch {
+        /// The programs to patch; those that don't need it are left alone
+        #[arg(required = true)]
+        programs: Vec<PathBuf>,
+    },
+    /// Remove staged build outputs and files left behind by interrupted builds
+    Clean {
+        /// Also remove the build cache, cached library lists and fetched sources
+        #[arg(long)]
+        all: bool,
+    },
 }
 
-/* unpak/bdeps */
-/* unpak/rdeps */
+/// unpak, the source-based package manager without dependency hell
+#[derive(Parser, Debug)]
+struct Arguments {
+    #[command(subcommand)]
+    action: Action,
 
-enum StdMountLocation {
-    UserExe,
-    UserSo,
-}
+    /// Print more detail, repeat for even more
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
 
-const FHS_EXE: &str = "/usr/bin";
-const FHS_SO: &str = "/usr/lib";
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
 
-#[allow(dead_code)]
-impl StdMountLocation {
-    fn into_absolute_path(self) -> PathBuf {
-        match self {
-            StdMountLocation::UserExe => FHS_EXE.into(),
-            StdMountLocation::UserSo => FHS_SO.into(),
-        }
-    }
+    /// Print what would be run without running it
+    #[arg(long, global = true)]
+    dry_run: bool,
 
-    fn to_absolute_path(&self) -> PathBuf {
-        match self {
-            StdMountLocation::UserExe => FHS_EXE.into(),
-            StdMountLocation::UserSo => FHS_SO.into(),
-        }
-    }
-}
+    /// Append a JSON object per event (commands, mounts, exits) to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    log_json: Option<PathBuf>,
 
-struct HostPath(pub PathBuf);
-struct SbxPath(pub PathBuf);
+    /// The directory of project manifests to use [default: ~/.local/share/unpak/registry]
+    #[arg(long, global = true, value_name = "DIR")]
+    registry: Option<PathBuf>,
 
-impl<T: Into<PathBuf>> From<T> for HostPath {
-    fn from(value: T) -> Self {
-        HostPath(value.into())
-    }
+    /// The bwrap to run sandboxes with [default: $UNPAK_BWRAP, or bwrap from PATH]
+    #[arg(long, global = true, value_name = "PATH")]
+    bwrap_path: Option<PathBuf>,
 }
 
-impl<T: Into<PathBuf>> From<T> for SbxPath {
-    fn from(value: T) -> Self {
-        SbxPath(value.into())
-    }
-}
+/// Logs to stderr as `[unpak] message`, tagging anything that isn't plain info with its level,
+/// above the progress line of a build.
+fn init_logger(args: &Arguments) {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
 
-/// Creates a symlink from `dest -> src`
-struct Symlink {
-    /// Where to create the symlink
-    dest: SbxPath,
-    /// Where the symlink points to
-    src: SbxPath,
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(env_logger::Target::Pipe(Box::new(progress::Stderr)))
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "[unpak] {}", record.args()),
+            Level::Warn => writeln!(buf, "[unpak] WARNING: {}", record.args()),
+            level => writeln!(buf, "[unpak] {level}: {}", record.args()),
+        })
+        .init();
 }
 
-enum Mount {
-    Touch {
-        sbx_path: SbxPath,
-    },
-    Fs {
-        readonly: bool,
-        host_path: HostPath,
-        sbx_path: SbxPath,
-    },
+fn load_registry(dir: &Path) -> Result<HashMap<ProjectId, SourceProject>, UnpakError> {
+    registry::load_registry(dir).map_err(UnpakError::io(format!(
+        "could not read the registry at {}",
+        dir.display()
+    )))
 }
 
-impl<A: Into<HostPath>, B: Into<SbxPath>> From<(A, B)> for Mount {
-    fn from((host, sbx): (A, B)) -> Self {
-        Mount::Fs {
-            readonly: true,
-            host_path: host.into(),
-            sbx_path: sbx.into(),
-        }
+fn run(args: Arguments) -> Result<i32, UnpakError> {
+    let config = Config::load(&config::config_path())?;
+    config.apply();
+    // the flag over the environment over the config file
+    let bwrap = args
+        .bwrap_path
+        .clone()
+        .or_else(|| {
+            std::env::var_os("UNPAK_BWRAP")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        })
+        .or(config.bwrap_path.clone());
+    if let Some(bwrap) = bwrap {
+        unpak::set_bwrap_path(bwrap);
     }
-}
-
-impl<T: Into<HostPath>> From<(T, StdMountLocation)> for Mount {
-    fn from((host, base_sbx): (T, StdMountLocation)) -> Self {
-        let host_path = host.into();
-        let filename = host_path.0.file_name().unwrap();
-        let sbx_path = base_sbx.into_absolute_path().join(filename).into();
 
-        Mount::Fs {
-            host_path,
-            sbx_path,
-            readonly: true,
-        }
+    if let Some(path) = &args.log_json {
+        events::init(path).map_err(UnpakError::io(format!("could not open {}", path.display())))?;
     }
-}
 
-struct Bubblewrap {
-    mounts: Vec<Mount>,
-    symlinks: Vec<Symlink>,
-    path: Option<OsString>,
-    chdir: Option<PathBuf>,
-    unshare_pid: bool,
-
-    new_session: bool,
-    detach_output: bool,
-
-    program: Option<PathBuf>,
-    envvars: EnvVars,
-}
-
-enum EnvVars {
-    Inherit,
-    Set(Vec<(OsString, OsString)>),
-}
-
-impl EnvVars {
-    fn set_mut(&mut self) -> &mut Vec<(OsString, OsString)> {
-        match self {
-            EnvVars::Inherit => panic!("cannot add new environment variables if `inherit` is set."),
-            EnvVars::Set(list) => list,
-        }
+    let needs_sandbox = matches!(
+        args.action,
+        Action::Build {
+            print_mounts: false,
+            ..
+        } | Action::Shell { .. }
+    );
+    if needs_sandbox && !args.dry_run {
+        unpak::ensure_tooling(false)?;
     }
-}
 
-#[allow(dead_code)]
-impl Bubblewrap {
-    fn new() -> Self {
-        Self {
-            mounts: Vec::new(),
-            symlinks: Vec::new(),
-            path: None,
-            chdir: None,
-            unshare_pid: false,
-            new_session: false,
-            detach_output: false,
-            program: None,
-            envvars: EnvVars::Inherit,
+    let registry_dir = args
+        .registry
+        .or(config.registry)
+        .unwrap_or_else(registry::default_registry_dir);
+    match args.action {
+        Action::Build {
+            project: project_path,
+            format,
+            jobs,
+            update,
+            fetch_retries,
+            fetch_timeout,
+            build_timeout,
+            timings,
+            restrict_mounts,
+            allow_path,
+            share_net,
+            unshare_net,
+            raw_output,
+            keep_temp,
+            source_date,
+            print_mounts,
+            only_phase,
+            skip_phase,
+            audit,
+            no_check,
+            allow_unset_vars,
+            no_std_symlinks,
+            output,
+        } => {
+            // the flags override each other, so at most one is set
+            let unshare_net = match (share_net, unshare_net) {
+                (true, _) => Some(false),
+                (_, true) => Some(true),
+                _ => None,
+            };
+            let options = BuildOptions {
+                dry_run: args.dry_run || print_mounts,
+                raw_output,
+                fetch_retries,
+                fetch_timeout: fetch_timeout.map(Duration::from_secs),
+                build_timeout: build_timeout.map(Duration::from_secs),
+                timings,
+                allowed_paths: restrict_mounts.then(|| unpak::allowed_paths(allow_path)),
+                output_to_stderr: output == BuildOutput::Json,
+                keep_temp,
+                source_date_epoch: source_date,
+                share_net: !unshare_net.or(config.unshare_net).unwrap_or(true),
+                print_mounts,
+                only_phases: only_phase,
+                skip_phases: skip_phase,
+                audit,
+                no_check,
+                allow_unset_vars,
+                no_std_symlinks,
+            };
+            if let Err(e) = interrupt::install() {
+                warn!("could not handle Ctrl-C, interrupting may leave sandboxes running: {e}");
+            }
+            let jobs = jobs.or(config.jobs).unwrap_or(1);
+            let result = unpak::build_project(
+                &project_path,
+                format,
+                load_registry(&registry_dir)?,
+                update,
+                jobs,
+                &options,
+            );
+            if output == BuildOutput::Json {
+                let summary = match &result {
+                    Ok(summary) => Some(summary),
+                    Err(UnpakError::Build(e)) => Some(e.summary()),
+                    Err(_) => None,
+                };
+                if let Some(summary) = summary {
+                    println!("{}", summary.to_json());
+                }
+            }
+            result?;
+            Ok(0)
         }
-    }
-
-    fn add_mount(&mut self, mount: Mount) -> &mut Self {
-        self.mounts.push(mount);
-        self
-    }
-
-    fn with_mount(mut self, mount: Mount) -> Self {
-        self.add_mount(mount);
-        self
-    }
-
-    fn add_mounts(&mut self, mounts: impl IntoIterator<Item = Mount>) {
-        self.mounts.extend(mounts);
-    }
-
-    fn with_mounts(mut self, mounts: impl IntoIterator<Item = Mount>) -> Self {
-        self.add_mounts(mounts);
-        self
-    }
-
-    fn add_symlink(&mut self, symlink: Symlink) -> &mut Self {
-        self.symlinks.push(symlink);
-        self
-    }
-
-    fn with_symlink(mut self, symlink: Symlink) -> Self {
-        self.add_symlink(symlink);
-        self
-    }
-
-    fn add_symlinks(&mut self, symlinks: impl IntoIterator<Item = Symlink>) -> &mut Self {
-        self.symlinks.extend(symlinks);
-        self
-    }
-
-    fn with_symlinks(mut self, symlinks: impl IntoIterator<Item = Symlink>) -> Self {
-        self.add_symlinks(symlinks);
-        self
-    }
-
-    fn set_program(&mut self, program: PathBuf) -> &mut Self {
-        self.program = Some(program);
-        self
-    }
-
-    fn with_program(mut self, program: PathBuf) -> Self {
-        self.set_program(program);
-        self
-    }
-
-    fn with_detach_stdout(mut self, detach_stdout: bool) -> Self {
-        self.detach_output = detach_stdout;
-        self
-    }
-
-    fn with_new_session(mut self, setsid: bool) -> Self {
-        self.new_session = setsid;
-        self
-    }
-
-    fn with_inherit_env(mut self, inherit: bool) -> Self {
-        self.envvars = if inherit {
-            EnvVars::Inherit
-        } else {
-            EnvVars::Set(Vec::new())
-        };
-        self
-    }
-
-    fn add_envvar(&mut self, id: OsString, value: OsString) -> &mut Self {
-        self.envvars.set_mut().push((id, value));
-        self
-    }
-
-    fn with_envvar(mut self, id: OsString, value: OsString) -> Self {
-        self.add_envvar(id, value);
-        self
-    }
-
-    fn spawn(self) -> io::Result<Child> {
-        let mut cmd = Command::new("bwrap");
-        for mount in self.mounts {
-            match mount {
-                Mount::Touch { sbx_path } => {
-                    cmd.args([OsStr::new("--dir"), sbx_path.0.as_os_str()]);
+        Action::List => {
+            let packages = state::load_packages()
+                .map_err(UnpakError::io("could not read the recorded packages"))?;
+            for package in packages {
+                let mut line = package.id.to_string();
+                if let Some(version) = &package.version {
+                    line = format!("{line} {version}");
                 }
-                Mount::Fs {
-                    readonly,
-                    host_path,
-                    sbx_path,
-                } => {
-                    let bind_flag: &OsStr = if readonly {
-                        OsStr::new("--ro-bind")
-                    } else {
-                        OsStr::new("--bind")
-                    };
-                    cmd.args([
-                        bind_flag,
-                        host_path.0.as_os_str(),
-                        sbx_path.0.as_os_str(),
-                    ]);
+                if let Some(root) = &package.root {
+                    line = format!("{line} (installed under {})", root.display());
                 }
+                println!("{line}");
             }
+            Ok(0)
         }
-
-        for symlink in self.symlinks {
-            cmd.args([
-                OsStr::new("--symlink"),
-                symlink.src.0.as_os_str(),
-                symlink.dest.0.as_os_str(),
-            ]);
+        Action::Install { id, root } => {
+            let installed = install::install(&id, &root, args.dry_run)
+                .map_err(UnpakError::io(format!("could not install {id}")))?;
+            let verb = if args.dry_run {
+                "would install"
+            } else {
+                "installed"
+            };
+            for path in &installed {
+                println!("{verb} {}", path.display());
+            }
+            Ok(0)
         }
-
-        if let Some(path) = self.path {
-            cmd.args([
-                OsStr::new("--set-env"),
-                OsStr::new("PATH"),
-                path.as_os_str(),
-            ]);
+        Action::Remove { id } => {
+            let removal = remove::remove(&id, args.dry_run)
+                .map_err(UnpakError::io(format!("could not remove {id}")))?;
+            let verb = if args.dry_run {
+                "would remove"
+            } else {
+                "removed"
+            };
+            for path in &removal.removed {
+                println!("{verb} {}", path.display());
+            }
+            for (path, owner) in &removal.shared {
+                println!("kept {}, {owner} has it too", path.display());
+            }
+            Ok(0)
         }
-
-        if let Some(chdir) = self.chdir {
-            cmd.args([OsStr::new("--chdir"), chdir.as_os_str()]);
+        Action::Search { query } => {
+            let projects = registry::read_manifests(&registry_dir).map_err(UnpakError::io(
+                format!("could not read the registry at {}", registry_dir.display()),
+            ))?;
+            for project in registry::search(&projects, &query) {
+                let mut line = project.id().to_string();
+                if let Some(version) = project.version() {
+                    line = format!("{line} {version}");
+                }
+                if let Some(description) = project.description() {
+                    line = format!("{line} - {description}");
+                }
+                println!("{line}");
+            }
+            Ok(0)
         }
-
-        if self.unshare_pid {
-            cmd.arg("--unshare-pid");
+        Action::Graph {
+            project: project_path,
+            format,
+        } => {
+            let project = unpak::load_manifest(&project_path)?;
+            let registry = load_registry(&registry_dir)?;
+            let graph = graph::dependency_graph(&project, &registry);
+            match format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Json => println!("{}", graph.to_json()),
+            }
+            Ok(0)
         }
-
-        if self.new_session {
-            eprintln!("[unpak] WARNING: setsid will break job control.");
-            cmd.arg("--new-session");
+        Action::Info {
+            project: project_path,
+            json,
+        } => {
+            let project = unpak::load_manifest(&project_path)?;
+            let registry = load_registry(&registry_dir)?;
+            let info = info::project_info(&project, &registry);
+            if json {
+                println!("{}", info.to_json());
+            } else {
+                print!("{}", info.to_report());
+            }
+            Ok(0)
         }
-
-        match self.envvars {
-            EnvVars::Inherit => eprintln!("[unpak] WARNING: environment variables are inherited"),
-            EnvVars::Set(list) => {
-                if list.is_empty() {
-                    cmd.arg("--clearenv");
-                } else {
-                    cmd.args(
-                        list.iter()
-                            .flat_map(|(id, value)| [OsStr::new("--setenv"), id, value]),
-                    );
+        Action::Doctor => Ok(if doctor::doctor() { 0 } else { 1 }),
+        Action::Patch { programs } => {
+            let mut failed = false;
+            for (program, result) in programs.iter().zip(unpak::patch_many(&programs)) {
+                match result {
+                    Ok(()) => println!("{}: ok", program.display()),
+                    Err(e) => {
+                        eprintln!("error: {e}");
+                        failed = true;
+                    }
                 }
             }
+            Ok(if failed { 1 } else { 0 })
         }
-
-        if !self.new_session && !self.detach_output {
-            eprintln!("[unpak] WARNING: sandbox escape may be possible because process can control terminal.");
+        Action::Clean { all } => {
+            let freed = clean::clean(all).map_err(UnpakError::io("could not clean up"))?;
+            println!("freed {freed} bytes");
+            Ok(0)
         }
-
-	cmd.arg("--die-with-parent");
-
-        cmd.arg(
-            self.program
-                .expect("a program to run in the sandbox is required"),
-        );
-
-        if self.detach_output {
-            cmd.stdout(Stdio::null());
-            cmd.stderr(Stdio::null());
+        Action::Shell { no_std_symlinks } => {
+            let status =
+                unpak::launch_shell(no_std_symlinks, args.dry_run).map_err(UnpakError::Sandbox)?;
+            // as shells report a child killed by a signal
+            Ok(status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal))
+                .unwrap_or(1))
         }
-
-        cmd.spawn()
     }
 }
 
-fn launch_bubblewrap(proc: &Path, mounts: impl IntoIterator<Item = Mount>) {
-    let mut builder = Bubblewrap::new();
-
-    // essential directories, even if empty.
-    builder.add_mount(Mount::Touch { sbx_path: "/usr/sbin".into() });
-    builder.add_mount(Mount::Touch { sbx_path: "/usr/bin".into() });
-
-    builder.add_mounts(mounts);
-
-    // ld-linux
-    builder.add_mount(Mount::Fs {
-        readonly: true,
-        host_path: INTERPRETER_HOST.into(),
-        sbx_path: SBX_LD_LINUX.into(),
-    });
-
-    builder.add_symlinks([
-        Symlink {
-            src: SBX_LD_LINUX.into(),
-            dest: "/usr/lib64/ld-linux-x86-64.so.2".into(),
-        },
-        Symlink {
-            src: "/usr/lib".into(),
-            dest: "/lib".into(),
-        },
-        Symlink {
-            src: "/usr/lib64".into(),
-            dest: "/lib64".into(),
-        },
-        Symlink {
-            src: "/usr/bin".into(),
-            dest: "/bin".into(),
-        },
-        Symlink {
-            src: "/usr/sbin".into(),
-            dest: "/sbin".into(),
-        },
-    ]);
-
-    let mut proc = builder
-        .with_program(proc.to_path_buf())
-        .with_inherit_env(false)
-        .spawn()
-        .unwrap();
-
-    let exit_code = proc.wait().unwrap();
-    eprintln!("[unpak] sandbox exited with code {exit_code}");
-}
-
-#[derive(Serialize, Deserialize)]
-struct BuildCmd {
-    program: PathBuf,
-    arguments: Vec<String>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum BuildProcess {
-    Cmds(Vec<BuildCmd>),
-}
-
-#[derive(Subcommand, Debug)]
-enum Action {
-    Build {
-        /// The project manifest file
-        project: PathBuf,
-    },
-}
-
-/// unpak, the source-based package manager without dependency hell
-#[derive(Parser, Debug)]
-struct Arguments {
-    #[command(subcommand)]
-    action: Action,
-}
-
 fn main() {
-    // let args = Arguments::parse();
-
-    //patch_bootstrap(Path::new("./bash"));
-    // TODO: Get ELF interpreter for current binary
-
-    #[rustfmt::skip]
-    let mounts = [
-	// begin shared libraries
-	(   // libtinfo, dependency of bash
-	    PathBuf::from("/usr/lib/x86_64-linux-gnu/libtinfo.so.6"),
-	    StdMountLocation::UserSo,
-	).into(),
-	(   // libc, dependency of bash
-	    PathBuf::from("/usr/lib/x86_64-linux-gnu/libc.so.6"),
-	    StdMountLocation::UserSo,
-	).into(),
-	(   // libselinux, dependency of ls
-	    PathBuf::from("/usr/lib/x86_64-linux-gnu/libselinux.so.1"),
-	    StdMountLocation::UserSo,
-	).into(),
-	(   // libpcre2-8
-	    PathBuf::from("/usr/lib/x86_64-linux-gnu/libpcre2-8.so.0"),
-	    StdMountLocation::UserSo,
-	).into(),
-
-	// begin executables
-	(   // bash
-	    PathBuf::from("/usr/bin/bash"),
-	    StdMountLocation::UserExe,
-	).into(),
-	(   // ls
-	    PathBuf::from("/usr/bin/ls"),
-	    StdMountLocation::UserExe,
-	).into()
-    ];
-
-    launch_bubblewrap(Path::new("/usr/bin/bash"), mounts);
-
-    /*match args.action {
-        Action::Build {
-            project: project_path,
-        } => {
-            let project: SourceProject =
-                toml::from_str(&std::fs::read_to_string(project_path).unwrap()).unwrap();
-            project.build();
+    let args = Arguments::parse();
+    init_logger(&args);
+
+    match run(args) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("error: {e}");
+            if interrupt::interrupted() {
+                std::process::exit(interrupt::EXIT_CODE);
+            }
+            std::process::exit(e.exit_code());
         }
     }
-
-    let project = SourceProject {
-        id: ProjectId("com.github.osten.unpak".to_string()),
-        build: BuildProcess::Cmds(vec![]),
-        libraries: vec![ProjectId("org.gnu.glibc".to_string())],
-    };
-    eprintln!("{}", toml::to_string_pretty(&project).unwrap());*/
 }
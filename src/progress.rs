@@ -0,0 +1,155 @@
+//! How far `unpak build` has got through its plan: how many projects are done, which are
+//! building, and for how long.
+//!
+//! On a terminal this is a line kept at the bottom of stderr, cleared before anything else unpak
+//! prints and drawn again after it. Elsewhere it goes to the log, a line whenever a project
+//! starts, so `--quiet` hides it either way.
+
+use crate::ProjectId;
+use log::{info, log_enabled, Level};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The progress line currently drawn on stderr, if any.
+static LINE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Runs `print` with the progress line cleared, drawing it again afterwards, so whatever `print`
+/// writes to stdout or stderr isn't mixed into the line.
+pub fn above<T>(print: impl FnOnce() -> T) -> T {
+    let line = LINE.lock().unwrap_or_else(|e| e.into_inner());
+    if line.is_some() {
+        let _ = io::stderr().write_all(b"\r\x1b[K");
+    }
+    let printed = print();
+    if let Some(line) = &*line {
+        let _ = io::stdout().flush();
+        let _ = write!(io::stderr(), "{line}");
+    }
+    printed
+}
+
+fn draw(new: Option<String>) {
+    let mut line = LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stderr = io::stderr().lock();
+    let _ = stderr.write_all(b"\r\x1b[K");
+    if let Some(new) = &new {
+        let _ = stderr.write_all(new.as_bytes());
+    }
+    let _ = stderr.flush();
+    *line = new;
+}
+
+/// Stderr for the logger, writing each record [`above`] the progress line.
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        above(|| io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        above(|| io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// The width of the terminal on stderr, in columns.
+fn terminal_width() -> usize {
+    // SAFETY: winsize is plain data, and TIOCGWINSZ only writes to it
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: as above
+    let found = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    match size.ws_col {
+        cols if found && cols > 0 => cols.into(),
+        _ => 80,
+    }
+}
+
+/// A duration to the second, as `1h02m03s`, `2m03s` or `3s`.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
+
+/// The progress of a build plan of `total` projects.
+pub struct Progress {
+    total: usize,
+    done: usize,
+    started: Instant,
+    running: Vec<(ProjectId, Instant)>,
+    /// Whether progress is drawn as a line on the terminal rather than logged.
+    on_terminal: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Progress {
+            total,
+            done: 0,
+            started: Instant::now(),
+            running: Vec::new(),
+            on_terminal: log_enabled!(Level::Info) && io::stderr().is_terminal(),
+        }
+    }
+
+    fn counter(&self) -> String {
+        format!(
+            "[{}/{} in {}]",
+            self.done,
+            self.total,
+            format_elapsed(self.started.elapsed())
+        )
+    }
+
+    pub fn started(&mut self, id: &ProjectId) {
+        self.running.push((id.clone(), Instant::now()));
+        if self.on_terminal {
+            self.redraw();
+        } else {
+            info!("{} building {id}", self.counter());
+        }
+    }
+
+    pub fn finished(&mut self, id: &ProjectId) {
+        self.running.retain(|(running, _)| running != id);
+        self.done += 1;
+        self.redraw();
+    }
+
+    /// Draws the progress line again to update the times on it.
+    pub fn redraw(&self) {
+        if !self.on_terminal {
+            return;
+        }
+        let building: Vec<String> = self
+            .running
+            .iter()
+            .map(|(id, started)| format!("{id} ({})", format_elapsed(started.elapsed())))
+            .collect();
+        let mut line = self.counter();
+        if !building.is_empty() {
+            line = format!("{line} building {}", building.join(", "));
+        }
+        // a line wrapping onto the next can't be cleared with a carriage return
+        if let Some((cut, _)) = line.char_indices().nth(terminal_width().saturating_sub(1)) {
+            line.truncate(cut);
+        }
+        draw(Some(line));
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.on_terminal {
+            draw(None);
+        }
+    }
+}
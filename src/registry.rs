@@ -0,0 +1,96 @@
+//! The registry of available projects: a directory of manifest files.
+//!
+//! Every `*.toml` and `*.json` file directly inside the registry directory is a project
+//! manifest, in the same format `unpak build` takes. The registry lives at
+//! `$XDG_DATA_HOME/unpak/registry` or `~/.local/share/unpak/registry` unless `--registry` or the
+//! config file says otherwise.
+//!
+//! The registry is shared by every project in it, so none builds in it: a project without a
+//! `source` builds from the directory next to its manifest named after it, `foo/` for
+//! `foo.toml`.
+
+use crate::{load_manifest, ProjectId, SourceProject};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The registry used without `--registry`.
+pub fn default_registry_dir() -> PathBuf {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+    data.join("unpak").join("registry")
+}
+
+/// Parses every manifest in the registry at `dir`, along with its path. A missing registry is
+/// empty.
+fn manifests(dir: &Path) -> io::Result<Vec<(PathBuf, SourceProject)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut projects = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_manifest = matches!(
+            path.extension().and_then(OsStr::to_str),
+            Some("toml" | "json")
+        );
+        if !is_manifest || !path.is_file() {
+            continue;
+        }
+        let mut project =
+            load_manifest(&path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // relative sources are already resolved against the registry, as the manifest's own dir
+        project.base_dir = path.with_extension("");
+        projects.push((path, project));
+    }
+    Ok(projects)
+}
+
+/// Parses every manifest in the registry at `dir`, sorted by id. A missing registry is empty.
+pub fn read_manifests(dir: &Path) -> io::Result<Vec<SourceProject>> {
+    let mut projects: Vec<SourceProject> = manifests(dir)?
+        .into_iter()
+        .map(|(_, project)| project)
+        .collect();
+    projects.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+    Ok(projects)
+}
+
+/// Parses every manifest in the registry at `dir` into projects by id, for resolving
+/// dependencies. Two manifests with the same id are an error, as neither can be preferred.
+pub fn load_registry(dir: &Path) -> io::Result<HashMap<ProjectId, SourceProject>> {
+    let mut registry = HashMap::new();
+    let mut found_in: HashMap<ProjectId, PathBuf> = HashMap::new();
+    for (path, project) in manifests(dir)? {
+        if let Some(first) = found_in.insert(project.id.clone(), path.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is defined by both {} and {}",
+                    project.id,
+                    first.display(),
+                    path.display()
+                ),
+            ));
+        }
+        registry.insert(project.id.clone(), project);
+    }
+    Ok(registry)
+}
+
+/// The projects of `projects` whose id contains `query`, ignoring case.
+pub fn search<'a>(projects: &'a [SourceProject], query: &str) -> Vec<&'a SourceProject> {
+    let query = query.to_lowercase();
+    projects
+        .iter()
+        .filter(|project| project.id.0.to_lowercase().contains(&query))
+        .collect()
+}
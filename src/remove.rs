@@ -0,0 +1,147 @@
+//! `unpak remove`: deleting the files a package put in place.
+//!
+//! What a package owns is the [`Package`](crate::state::Package) recorded for it, with paths
+//! relative to the root [`install`](crate::install::install) put it under. Packages that were only
+//! staged have no files anywhere else, so there is nothing to remove. Files that another package installed under the
+//! same root owns too are kept, and directories are only removed once nothing else is left in
+//! them.
+
+use crate::state::{self, EntryKind};
+use crate::ProjectId;
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// What [`remove`] did with the entries of a package.
+#[derive(Debug, Default)]
+pub struct Removal {
+    /// The files, symlinks and directories removed, or that would be in a dry run.
+    pub removed: Vec<PathBuf>,
+    /// Files kept because another package owns them too, along with that package.
+    pub shared: Vec<(PathBuf, ProjectId)>,
+}
+
+/// Removes what the package `id` recorded from under the root it was installed under, then the
+/// records of `id`. Entries already gone are skipped. With `dry_run`, only reports what would be
+/// removed, including directories that other files would keep in place.
+pub fn remove(id: &ProjectId, dry_run: bool) -> io::Result<Removal> {
+    let not_installed = || io::Error::new(io::ErrorKind::NotFound, "it is not installed");
+    if !id.is_file_name() {
+        return Err(not_installed());
+    }
+    let package = state::load_package(id).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => not_installed(),
+        _ => e,
+    })?;
+    let Some(root) = &package.root else {
+        return Err(not_installed());
+    };
+    let mut owners: HashMap<PathBuf, ProjectId> = HashMap::new();
+    for other in state::load_packages()? {
+        if other.id == *id || other.root.as_ref() != Some(root) {
+            continue;
+        }
+        for entry in other.entries {
+            if entry.kind != EntryKind::Dir {
+                owners.insert(entry.path, other.id.clone());
+            }
+        }
+    }
+
+    let mut removal = Removal::default();
+    // entries are sorted by path, so backwards empties each directory before reaching it
+    for entry in package.entries.iter().rev() {
+        if !entry.is_inside_root() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{id} records {}, which is not inside its root",
+                    entry.path.display()
+                ),
+            ));
+        }
+        let path = root.join(&entry.path);
+        if let Some(owner) = owners.get(&entry.path) {
+            removal.shared.push((path, owner.clone()));
+            continue;
+        }
+        if dry_run {
+            if path.symlink_metadata().is_ok() {
+                removal.removed.push(path);
+            }
+            continue;
+        }
+        debug!("removing {}", path.display());
+        let removed = match entry.kind {
+            EntryKind::Dir => std::fs::remove_dir(&path),
+            EntryKind::File { .. } | EntryKind::Symlink { .. } => std::fs::remove_file(&path),
+        };
+        match removed {
+            Ok(()) => removal.removed.push(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            // still holding files of other packages, or of nobody
+            Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if !dry_run {
+        state::forget(id)?;
+    }
+    Ok(removal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{install, stage, test_dirs};
+
+    /// Stages `files` for `id` and records them, as a successful build of it does.
+    fn build(id: &ProjectId, files: &[(&str, &str)]) {
+        let staged = stage::reset(id).unwrap();
+        for (path, contents) in files {
+            let path = staged.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let package = state::Package {
+            id: id.clone(),
+            version: None,
+            root: None,
+            entries: stage::scan(&staged).unwrap(),
+        };
+        state::save_package(&package).unwrap();
+    }
+
+    #[test]
+    fn keeps_files_another_package_owns() {
+        let root = test_dirs().join("remove-root");
+        let tool = ProjectId::from("remove.tool");
+        let other = ProjectId::from("remove.other");
+        build(&tool, &[("usr/bin/tool", "1"), ("usr/share/common", "1")]);
+        build(&other, &[("usr/share/common", "b")]);
+        install::install(&tool, &root, false).unwrap();
+        install::install(&other, &root, false).unwrap();
+
+        let removal = remove(&tool, false).unwrap();
+        assert!(removal.removed.contains(&root.join("usr/bin/tool")));
+        assert_eq!(
+            removal.shared,
+            vec![(root.join("usr/share/common"), other.clone())]
+        );
+        assert!(!root.join("usr/bin").exists());
+        assert!(root.join("usr/share/common").exists());
+        assert!(state::load_package(&tool).is_err());
+        assert!(remove(&tool, false).is_err());
+        assert_eq!(remove(&other, true).unwrap().shared, []);
+    }
+
+    #[test]
+    fn refuses_packages_only_staged() {
+        let id = ProjectId::from("remove.staged");
+        build(&id, &[("usr/bin/staged", "")]);
+        let e = remove(&id, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(stage::staging_dir(&id).join("usr/bin/staged").exists());
+    }
+}
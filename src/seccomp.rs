@@ -0,0 +1,189 @@
+//! Syscall filtering for sandboxed builds.
+//!
+//! bwrap's `--seccomp FD` loads a filter from a file descriptor. The file must hold a compiled
+//! classic BPF program: an array of `struct sock_filter` (a `u16` code, `u8` jump-if-true,
+//! `u8` jump-if-false and `u32` constant, 8 bytes in all) in native byte order, with no header.
+//! That is what `prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, ...)` takes, and what
+//! `seccomp_export_bpf` from libseccomp writes.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Syscalls builds have no business making: they reconfigure the kernel or the sandbox itself,
+/// or inspect other processes. Denied ones fail with `EPERM`.
+#[cfg(target_arch = "x86_64")]
+const DENIED: &[(u32, &str)] = &[
+    (101, "ptrace"),
+    (103, "syslog"),
+    (155, "pivot_root"),
+    (163, "acct"),
+    (165, "mount"),
+    (166, "umount2"),
+    (167, "swapon"),
+    (168, "swapoff"),
+    (169, "reboot"),
+    (170, "sethostname"),
+    (171, "setdomainname"),
+    (172, "iopl"),
+    (173, "ioperm"),
+    (175, "init_module"),
+    (176, "delete_module"),
+    (179, "quotactl"),
+    (246, "kexec_load"),
+    (248, "add_key"),
+    (249, "request_key"),
+    (250, "keyctl"),
+    (272, "unshare"),
+    (298, "perf_event_open"),
+    (304, "open_by_handle_at"),
+    (308, "setns"),
+    (310, "process_vm_readv"),
+    (311, "process_vm_writev"),
+    (313, "finit_module"),
+    (320, "kexec_file_load"),
+    (321, "bpf"),
+    (323, "userfaultfd"),
+];
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+/// Syscall numbers at or above this are the x32 ABI, which would dodge the filter.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const EPERM: u32 = 1;
+
+/// Offsets into `struct seccomp_data`.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+
+fn insn(code: u16, jt: u8, jf: u8, k: u32) -> [u8; 8] {
+    let mut insn = [0; 8];
+    insn[..2].copy_from_slice(&code.to_ne_bytes());
+    insn[2] = jt;
+    insn[3] = jf;
+    insn[4..].copy_from_slice(&k.to_ne_bytes());
+    insn
+}
+
+/// The compiled default filter, denying [`DENIED`] and allowing everything else. A program of
+/// another architecture is killed outright, as its syscall numbers mean different things.
+#[cfg(target_arch = "x86_64")]
+pub fn default_filter() -> Option<Vec<u8>> {
+    let mut program = vec![
+        insn(BPF_LD_W_ABS, 0, 0, DATA_ARCH),
+        insn(BPF_JEQ_K, 1, 0, AUDIT_ARCH),
+        insn(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+        insn(BPF_LD_W_ABS, 0, 0, DATA_NR),
+    ];
+    // every check jumps to the final deny when it matches
+    let checks = DENIED.len() + 1;
+    program.push(insn(BPF_JGE_K, checks as u8, 0, X32_SYSCALL_BIT));
+    for (i, (nr, _)) in DENIED.iter().enumerate() {
+        program.push(insn(BPF_JEQ_K, (checks - i - 1) as u8, 0, *nr));
+    }
+    program.push(insn(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+    program.push(insn(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO | EPERM));
+    Some(program.concat())
+}
+
+/// There's no default filter for this architecture yet.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn default_filter() -> Option<Vec<u8>> {
+    None
+}
+
+/// Writes [`default_filter`] into unpak's cache directory and returns its path, or `None` when
+/// there is no default filter for this architecture.
+pub fn default_filter_path() -> io::Result<Option<PathBuf>> {
+    let Some(filter) = default_filter() else {
+        return Ok(None);
+    };
+    let path = crate::cache::cache_dir().join("seccomp-default.bpf");
+    if std::fs::read(&path).is_ok_and(|current| current == filter) {
+        return Ok(Some(path));
+    }
+
+    // concurrent builds may be writing it too, so write elsewhere and rename over it
+    std::fs::create_dir_all(crate::cache::cache_dir())?;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let partial = path.with_extension(format!(
+        "bpf.{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&partial, filter)?;
+    std::fs::rename(&partial, &path)?;
+    Ok(Some(path))
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    /// Runs the filter on a syscall `nr` of `arch`, as the kernel would, returning its verdict.
+    fn run(program: &[u8], arch: u32, nr: u32) -> u32 {
+        let insns: Vec<(u16, u8, u8, u32)> = program
+            .chunks_exact(8)
+            .map(|insn| {
+                let code = u16::from_ne_bytes([insn[0], insn[1]]);
+                let k = u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]);
+                (code, insn[2], insn[3], k)
+            })
+            .collect();
+        let (mut pc, mut acc) = (0, 0);
+        loop {
+            let (code, jt, jf, k) = insns[pc];
+            pc += 1;
+            let jump = match code {
+                BPF_LD_W_ABS => {
+                    acc = if k == DATA_ARCH { arch } else { nr };
+                    0
+                }
+                BPF_JEQ_K => {
+                    if acc == k {
+                        jt
+                    } else {
+                        jf
+                    }
+                }
+                BPF_JGE_K => {
+                    if acc >= k {
+                        jt
+                    } else {
+                        jf
+                    }
+                }
+                BPF_RET_K => return k,
+                _ => panic!("unexpected instruction {code:#x}"),
+            };
+            pc += usize::from(jump);
+            assert!(pc < insns.len(), "jumped past the end");
+        }
+    }
+
+    #[test]
+    fn denies_exactly_the_listed_syscalls() {
+        let program = default_filter().unwrap();
+        let denied = SECCOMP_RET_ERRNO | EPERM;
+        for &(nr, name) in DENIED {
+            assert_eq!(run(&program, AUDIT_ARCH, nr), denied, "{name}");
+        }
+        // read, write and execve
+        for nr in [0, 1, 59] {
+            assert_eq!(run(&program, AUDIT_ARCH, nr), SECCOMP_RET_ALLOW, "{nr}");
+        }
+        assert_eq!(run(&program, AUDIT_ARCH, X32_SYSCALL_BIT | 1), denied);
+        // i386
+        assert_eq!(run(&program, 0x4000_0003, 1), SECCOMP_RET_KILL_PROCESS);
+    }
+}
@@ -0,0 +1,111 @@
+//! Staging trees that builds install into.
+//!
+//! A build installs with `make install DESTDIR=$UNPAK_DESTDIR` or similar, into a per-project
+//! staging directory bound into the sandbox. The staging tree mirrors the final filesystem:
+//! `$UNPAK_DESTDIR/usr/bin/foo` is installed as `/usr/bin/foo`. Builds are told the prefix they
+//! should configure for as `$UNPAK_PREFIX`, so staged files land under
+//! `$UNPAK_DESTDIR$UNPAK_PREFIX`.
+//!
+//! Once a build succeeds, the files it staged are recorded next to the staging directory as
+//! `<id>.files.json`, a JSON list of paths relative to the staging tree.
+
+use crate::cache::cache_dir;
+use crate::state::{EntryKind, PackageEntry};
+use crate::ProjectId;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Where staging trees are kept, one directory per project.
+pub fn staging_root() -> PathBuf {
+    cache_dir().join("staging")
+}
+
+/// The staging tree of `id`.
+pub fn staging_dir(id: &ProjectId) -> PathBuf {
+    staging_root().join(&id.0)
+}
+
+/// Empties the staging tree of `id` so a build starts from nothing, creating it if needed.
+pub fn reset(id: &ProjectId) -> io::Result<PathBuf> {
+    let dir = staging_dir(id);
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Every file, symlink and empty directory under `dir`, relative to it and sorted.
+pub fn list_staged(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        let mut empty = true;
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            empty = false;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(relative(&entry.path(), dir));
+            }
+        }
+        if empty && current != dir {
+            files.push(relative(&current, dir));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Every file, directory and symlink under `dir`, relative to it and sorted, with its size and
+/// mode. Symlinks are recorded rather than followed.
+pub fn scan(dir: &Path) -> io::Result<Vec<PackageEntry>> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            let metadata = std::fs::symlink_metadata(&path)?;
+            let file_type = metadata.file_type();
+            let kind = if file_type.is_symlink() {
+                EntryKind::Symlink {
+                    target: std::fs::read_link(&path)?,
+                }
+            } else if file_type.is_dir() {
+                dirs.push(path.clone());
+                EntryKind::Dir
+            } else {
+                EntryKind::File {
+                    size: metadata.len(),
+                }
+            };
+            entries.push(PackageEntry {
+                path: relative(&path, dir),
+                mode: metadata.permissions().mode() & 0o7777,
+                kind,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn relative(path: &Path, dir: &Path) -> PathBuf {
+    path.strip_prefix(dir).expect("walked from dir").to_owned()
+}
+
+/// The files recorded as staged for `id` by its last successful build.
+pub fn read_staged(id: &ProjectId) -> io::Result<Vec<PathBuf>> {
+    let text = std::fs::read(staging_root().join(format!("{id}.files.json")))?;
+    Ok(serde_json::from_slice(&text)?)
+}
+
+/// Records the files staged for `id`, replacing any earlier record.
+pub fn record_staged(id: &ProjectId, files: &[PathBuf]) -> io::Result<()> {
+    let text = serde_json::to_string_pretty(files)?;
+    std::fs::write(staging_root().join(format!("{id}.files.json")), text)
+}
@@ -0,0 +1,134 @@
+//! Bookkeeping of built and installed projects.
+//!
+//! What the last successful build of a project staged, or what it installed, is recorded as
+//! `<state dir>/packages/<id>.json`, holding a [`Package`].
+
+use crate::ProjectId;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// What a build staged: every file, directory and symlink under its staging tree.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Package {
+    pub id: ProjectId,
+    pub version: Option<Version>,
+    /// The directory the entries were installed under, or `None` while they are only staged.
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    /// Sorted by path.
+    pub entries: Vec<PackageEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PackageEntry {
+    /// Relative to the staging tree, so also to the root once installed.
+    pub path: PathBuf,
+    /// The permission bits, including setuid, setgid and sticky. Always `0o777` for symlinks.
+    pub mode: u32,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+}
+
+impl PackageEntry {
+    /// Whether the path stays inside the root it is relative to, with no `..`, `/` or `.` in it.
+    pub fn is_inside_root(&self) -> bool {
+        self.path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A regular file of `size` bytes.
+    File {
+        size: u64,
+    },
+    Dir,
+    Symlink {
+        target: PathBuf,
+    },
+}
+
+/// The directory unpak keeps its state in, `$XDG_STATE_HOME/unpak` or `~/.local/state/unpak`.
+pub fn state_dir() -> PathBuf {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+    state.join("unpak")
+}
+
+fn packages_dir() -> PathBuf {
+    state_dir().join("packages")
+}
+
+/// Records what a build staged, replacing the record of any earlier build of the same id, unless
+/// that one was installed: the files installed are what it still has to account for.
+pub fn save_package(package: &Package) -> io::Result<()> {
+    match load_package(&package.id) {
+        Ok(Package { root: Some(_), .. }) => return Ok(()),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    write_package(package)
+}
+
+/// Records `package` as installed under its `root`, replacing any earlier record of its id.
+pub fn save_installed(package: &Package) -> io::Result<()> {
+    write_package(package)
+}
+
+fn write_package(package: &Package) -> io::Result<()> {
+    let dir = packages_dir();
+    std::fs::create_dir_all(&dir)?;
+    let text = serde_json::to_string_pretty(package)?;
+    std::fs::write(dir.join(format!("{}.json", package.id)), text)
+}
+
+fn read_package(path: &Path) -> io::Result<Package> {
+    let text = std::fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })
+}
+
+/// What the last successful build of `id` staged.
+pub fn load_package(id: &ProjectId) -> io::Result<Package> {
+    read_package(&packages_dir().join(format!("{id}.json")))
+}
+
+/// Every recorded [`Package`], sorted by id. A missing state directory means nothing was built.
+pub fn load_packages() -> io::Result<Vec<Package>> {
+    let entries = match std::fs::read_dir(packages_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut packages = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("json")) {
+            packages.push(read_package(&path)?);
+        }
+    }
+    packages.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+    Ok(packages)
+}
+
+/// Drops the record of what `id` staged or installed.
+pub fn forget(id: &ProjectId) -> io::Result<()> {
+    match std::fs::remove_file(packages_dir().join(format!("{id}.json"))) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,100 @@
+//! What happened to each project of a build, printed as JSON by `unpak build --output json`.
+
+use crate::{BuildFailure, ProjectFailure, ProjectId};
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Fetched and built.
+    Built,
+    /// Restored from the build cache without building.
+    Cached,
+    /// Would have been built, in a dry run.
+    Planned,
+    Failed,
+    /// Not started because a dependency failed or the build was interrupted.
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct ProjectSummary {
+    id: ProjectId,
+    outcome: Outcome,
+    duration_secs: f64,
+    /// The exit code of the build command that failed, if it exited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    /// The signal that killed the build command that failed, if one did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The projects of a build plan in the order they finished, followed by those never started.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildSummary {
+    projects: Vec<ProjectSummary>,
+}
+
+impl BuildSummary {
+    pub fn record(
+        &mut self,
+        id: &ProjectId,
+        result: Result<Outcome, &ProjectFailure>,
+        duration: Duration,
+    ) {
+        let (outcome, exit_code, signal, error) = match result {
+            Ok(outcome) => (outcome, None, None, None),
+            Err(failure) => {
+                let (exit_code, signal) = match failure {
+                    ProjectFailure::Build(e) => match &e.failure {
+                        BuildFailure::Exit(status) => (status.code(), None),
+                        BuildFailure::Signal(signal) => (None, Some(*signal)),
+                        _ => (None, None),
+                    },
+                    _ => (None, None),
+                };
+                (
+                    Outcome::Failed,
+                    exit_code,
+                    signal,
+                    Some(failure.to_string()),
+                )
+            }
+        };
+        self.projects.push(ProjectSummary {
+            id: id.clone(),
+            outcome,
+            duration_secs: duration.as_secs_f64(),
+            exit_code,
+            signal,
+            error,
+        });
+    }
+
+    pub fn record_skipped(&mut self, id: &ProjectId) {
+        self.projects.push(ProjectSummary {
+            id: id.clone(),
+            outcome: Outcome::Skipped,
+            duration_secs: 0.0,
+            exit_code: None,
+            signal: None,
+            error: None,
+        });
+    }
+
+    /// How many projects had `outcome`.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.projects
+            .iter()
+            .filter(|project| project.outcome == outcome)
+            .count()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("build summaries serialize to JSON")
+    }
+}